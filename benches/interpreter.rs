@@ -0,0 +1,78 @@
+//! Runs a few loop and call heavy programs and reports how long they take.
+//! Use `cargo bench` to run it.
+use std::{rc::Rc, time::Instant};
+
+use aoc_lang::aoc::compile_and_run;
+
+const RUNS: u32 = 5;
+
+fn bench(name: &str, code: &str) {
+    let code: Rc<str> = Rc::from(code);
+    let mut best = None;
+    for _ in 0..RUNS {
+        let mut output = Vec::new();
+        let start = Instant::now();
+        compile_and_run(code.clone(), &mut output);
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |b: std::time::Duration| b.min(elapsed)));
+    }
+    println!("{name:<20} {:>10.2?}", best.unwrap());
+}
+
+fn main() {
+    bench(
+        "vector sum",
+        "v = [1] * 1000000
+i = 0
+s = 0
+while i < +v {
+    s = s + v[i]
+    i = i + 1
+}
+print(s)",
+    );
+    bench(
+        "deep recursion",
+        "offset = 1
+depth = fn(n) {
+    step = fn() n - offset
+    if n == 0 0 else 1 + depth(step())
+}
+i = 0
+s = 0
+while i < 100 {
+    s += depth(5000)
+    i += 1
+}
+print(s)",
+    );
+    bench(
+        "fibonacci",
+        "fib = fn(n) if n < 2 n else fib(n - 1) + fib(n - 2)
+print(fib(25))",
+    );
+    bench(
+        "grid vector keys",
+        "g = {=}
+for y = 0; y < 300; y += 1
+    for x = 0; x < 300; x += 1
+        g[[x, y]] = x * y
+s = 0
+for y = 0; y < 300; y += 1
+    for x = 0; x < 300; x += 1
+        s += g[[x, y]]
+print(s)",
+    );
+    bench(
+        "grid packed keys",
+        "g = {=}
+for y = 0; y < 300; y += 1
+    for x = 0; x < 300; x += 1
+        grid_set(g, x, y, x * y)
+s = 0
+for y = 0; y < 300; y += 1
+    for x = 0; x < 300; x += 1
+        s += grid_get(g, x, y)
+print(s)",
+    );
+}
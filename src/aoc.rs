@@ -1,18 +1,118 @@
 use std::rc::Rc;
 
-use crate::{error, interpreter::Interpreter, lexer::Lexer, parser::Parser, runtime::Value};
+use crate::{
+    cache::Cache,
+    error,
+    expr::Expr,
+    interpreter::Interpreter,
+    lexer::Lexer,
+    parser::Parser,
+    runtime::{Chunk, Value},
+};
 use wasm_bindgen::prelude::*;
 
-pub fn compile_and_run<W: std::io::Write>(code: Rc<str>, mut output: W) -> Value {
-    let tokens = Lexer::new(code.clone());
-    let expr = match Parser::new(tokens).parse() {
-        Ok(expr) => expr,
+pub fn compile_and_run<W: std::io::Write>(code: Rc<str>, output: W) -> Value {
+    run_named(None, code, RunOptions::default(), output)
+}
+
+/// How [`run_named`] compiles and runs a program.
+#[derive(Default)]
+pub struct RunOptions<'a> {
+    /// Variables have to be declared before they are assigned to, warnings
+    /// are errors and `print` returns `nil`.
+    pub strict: bool,
+    /// Writes the tokens, syntax tree and bytecode before running, and the
+    /// stack before every operation.
+    pub debug: bool,
+    /// Writes a histogram of the executed operations here after running.
+    pub profile: Option<&'a mut dyn std::io::Write>,
+    /// Loads the compiled chunk from here if the code was already compiled
+    /// before. Not used when debugging, which has to show the compilation.
+    pub cache: Option<&'a Cache>,
+}
+
+/// Compiles and runs the code, labeling error traces with `source_name`.
+pub fn run_named<W: std::io::Write>(
+    source_name: Option<Rc<str>>,
+    code: Rc<str>,
+    options: RunOptions,
+    mut output: W,
+) -> Value {
+    let cache = options.cache.filter(|_| !options.debug);
+    let cached = cache.and_then(|c| c.load(source_name.as_ref(), &code, options.strict));
+    let chunk = match cached {
+        Some(chunk) => chunk,
+        None => {
+            let Some(chunk) = compile(
+                source_name,
+                code,
+                options.strict,
+                options.debug,
+                &mut output,
+            ) else {
+                return Value::Nil;
+            };
+            if let Some(cache) = cache {
+                cache.store(&chunk);
+            }
+            chunk
+        }
+    };
+    if options.debug {
+        write!(output, "=== Runtime ===\n{chunk}").unwrap();
+        writeln!(output, "=== Stdout ===").unwrap();
+    }
+    let mut ex = Interpreter::new(Rc::new(chunk), &mut output);
+    ex.set_debug(options.debug);
+    if options.profile.is_some() {
+        ex.enable_profile();
+    }
+    let result = ex.run();
+    let profile = ex.take_profile();
+    let value = match result {
+        Ok(value) => value,
         Err(e) => {
-            dump_err(output, e);
-            return Value::Nil;
+            dump_err(&mut output, e);
+            Value::Nil
         }
     };
-    let chunk = match expr.to_chunk(expr.code.clone().into()) {
+    if let (Some(report), Some(profile)) = (options.profile, profile) {
+        write!(report, "=== Profile ===\n{profile}").unwrap();
+    }
+    value
+}
+
+/// Parses and compiles the code without running it. Returns whether it
+/// compiled, errors and warnings are written to `output`.
+pub fn check_named<W: std::io::Write>(
+    source_name: Option<Rc<str>>,
+    code: Rc<str>,
+    strict: bool,
+    mut output: W,
+) -> bool {
+    compile(source_name, code, strict, false, &mut output).is_some()
+}
+
+/// Compiles the code into the binary format of [`Chunk::to_bytes`].
+pub fn compile_to_bytes<W: std::io::Write>(
+    source_name: Option<Rc<str>>,
+    code: Rc<str>,
+    strict: bool,
+    mut output: W,
+) -> Option<Vec<u8>> {
+    let chunk = compile(source_name, code, strict, false, &mut output)?;
+    match chunk.to_bytes() {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            dump_err(output, e);
+            None
+        }
+    }
+}
+
+/// Runs a chunk that was compiled with [`compile_to_bytes`].
+pub fn run_bytes<W: std::io::Write>(bytes: &[u8], mut output: W) -> Value {
+    let chunk = match Chunk::from_bytes(bytes) {
         Ok(chunk) => chunk,
         Err(e) => {
             dump_err(output, e);
@@ -29,11 +129,63 @@ pub fn compile_and_run<W: std::io::Write>(code: Rc<str>, mut output: W) -> Value
     }
 }
 
+/// Parses the code into its syntax tree without compiling it, for tools that
+/// work with the source rather than run it.
+pub fn parse(code: &str) -> error::Result<Expr, error::ParserError> {
+    Parser::new(Lexer::new(Rc::from(code))).parse()
+}
+
+/// Parses and compiles the code, writing any errors to `output`. When
+/// debugging the tokens and syntax tree are written as well.
+fn compile<W: std::io::Write>(
+    source_name: Option<Rc<str>>,
+    code: Rc<str>,
+    strict: bool,
+    debug: bool,
+    output: &mut W,
+) -> Option<Chunk> {
+    let tokens = Lexer::new(code);
+    if debug {
+        writeln!(output, "=== Tokens ===").unwrap();
+        tokens
+            .clone()
+            .for_each(|t| writeln!(output, "{:?}", t.kind).unwrap());
+    }
+    let expr = match Parser::new(tokens).parse() {
+        Ok(expr) => expr,
+        Err(e) => {
+            dump_err(output, e);
+            return None;
+        }
+    };
+    if debug {
+        writeln!(output, "=== Expression ===\n{:#?}", expr).unwrap();
+    }
+    let mut chunk = Chunk::named(expr.code.clone(), source_name);
+    chunk.strict = strict;
+    match expr.to_chunk(chunk) {
+        Ok(chunk) => {
+            dump_warnings(output, &chunk);
+            Some(chunk)
+        }
+        Err(e) => {
+            dump_err(output, e);
+            None
+        }
+    }
+}
+
 fn dump_err<W: std::io::Write, K: error::Kind>(mut stdout: W, err: error::Error<K>) {
     writeln!(stdout, "=== Stderr ===").unwrap();
     writeln!(stdout, "{}", err).unwrap();
 }
 
+fn dump_warnings<W: std::io::Write>(mut stdout: W, chunk: &Chunk) {
+    for warning in chunk.warnings.borrow().iter() {
+        writeln!(stdout, "=== Warning ===\n{warning}").unwrap();
+    }
+}
+
 #[wasm_bindgen]
 pub fn run(code: &str, debug: bool) -> String {
     let mut stdout = Vec::new();
@@ -46,39 +198,10 @@ pub fn run(code: &str, debug: bool) -> String {
     String::from_utf8_lossy(&stdout).to_string()
 }
 
-pub fn debug_run<W: std::io::Write>(code: Rc<str>, mut output: W) -> Value {
-    let tokens = Lexer::new(code.clone());
-    writeln!(output, "=== Tokens ===").unwrap();
-    tokens
-        .clone()
-        .into_iter()
-        .for_each(|t| writeln!(output, "{:?}", t.kind).unwrap());
-    let expr = match Parser::new(tokens).parse() {
-        Ok(expr) => expr,
-        Err(e) => {
-            let mut output = output;
-            dump_err(&mut output, e);
-            return Value::Nil;
-        }
-    };
-    writeln!(output, "=== Expression ===\n{:#?}", expr).unwrap();
-    let chunk = match expr.to_chunk(expr.code.clone().into()) {
-        Ok(chunk) => chunk,
-        Err(e) => {
-            let mut output = output;
-            dump_err(&mut output, e);
-            return Value::Nil;
-        }
+pub fn debug_run<W: std::io::Write>(code: Rc<str>, output: W) -> Value {
+    let options = RunOptions {
+        debug: true,
+        ..RunOptions::default()
     };
-    write!(output, "=== Runtime ===\n{chunk}").unwrap();
-    writeln!(output, "=== Stdout ===").unwrap();
-    let mut ex = Interpreter::new(Rc::new(chunk), &mut output);
-    ex.set_debug(true);
-    match ex.run() {
-        Ok(value) => value,
-        Err(e) => {
-            dump_err(&mut output, e);
-            Value::Nil
-        }
-    }
+    run_named(None, code, options, output)
 }
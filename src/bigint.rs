@@ -0,0 +1,417 @@
+//! Arbitrary precision integers. Integer arithmetic switches to them when a
+//! result doesn't fit into an `i64`.
+use std::{cmp::Ordering, fmt::Display};
+
+/// Sign and magnitude, stored as little endian base 2^32 digits without
+/// leading zeros. Zero is never negative.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    mag: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut mag: Vec<u32>) -> Self {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        Self {
+            negative: negative && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    /// Parses a decimal number with an optional leading minus, or returns
+    /// `None` if it contains anything else.
+    pub fn parse(s: &str) -> Option<BigInt> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let mut mag: Vec<u32> = Vec::new();
+        for c in digits.bytes() {
+            let mut carry = (c - b'0') as u64;
+            for d in mag.iter_mut() {
+                let v = *d as u64 * 10 + carry;
+                *d = v as u32;
+                carry = v >> 32;
+            }
+            if carry > 0 {
+                mag.push(carry as u32);
+            }
+        }
+        Some(BigInt::new(negative, mag))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let mag = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0i128, |acc, &d| (acc << 32) | d as i128);
+        i64::try_from(if self.negative { -mag } else { mag }).ok()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mag = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &d| acc * 4294967296.0 + d as f64);
+        if self.negative {
+            -mag
+        } else {
+            mag
+        }
+    }
+
+    /// Truncated division like `/` and `%` on `i64`, or `None` when dividing
+    /// by zero.
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = div_rem_mag(&self.mag, &other.mag);
+        Some((
+            BigInt::new(self.negative != other.negative, q),
+            BigInt::new(self.negative, r),
+        ))
+    }
+
+    /// Division rounded towards negative infinity, or `None` when dividing by
+    /// zero.
+    pub fn div_floor(&self, other: &BigInt) -> Option<BigInt> {
+        let (q, r) = self.div_rem(other)?;
+        if !r.is_zero() && self.negative != other.negative {
+            Some(&q - &BigInt::from(1i64))
+        } else {
+            Some(q)
+        }
+    }
+
+    /// Remainder of `div_floor`, which has the sign of the divisor, or `None`
+    /// when dividing by zero.
+    pub fn rem_floor(&self, other: &BigInt) -> Option<BigInt> {
+        let (_, r) = self.div_rem(other)?;
+        if !r.is_zero() && self.negative != other.negative {
+            Some(&r + other)
+        } else {
+            Some(r)
+        }
+    }
+
+    /// Multiplies by `2^n`.
+    pub fn shl(&self, n: usize) -> BigInt {
+        let mut mag = vec![0u32; n / 32];
+        let bits = n % 32;
+        let mut carry = 0u32;
+        for &d in &self.mag {
+            if bits == 0 {
+                mag.push(d);
+            } else {
+                mag.push((d << bits) | carry);
+                carry = d >> (32 - bits);
+            }
+        }
+        mag.push(carry);
+        BigInt::new(self.negative, mag)
+    }
+
+    /// Divides by `2^n`, rounding towards negative infinity like `>>` on
+    /// `i64`.
+    pub fn shr(&self, n: usize) -> BigInt {
+        let (words, bits) = (n / 32, n % 32);
+        let kept = match self.mag.get(words..) {
+            Some(kept) if !kept.is_empty() => kept,
+            _ => return BigInt::from(if self.negative { -1i64 } else { 0 }),
+        };
+        let mut lost = self.mag[..words].iter().any(|&d| d != 0);
+        let mut mag = Vec::with_capacity(kept.len());
+        for (i, &d) in kept.iter().enumerate() {
+            if bits == 0 {
+                mag.push(d);
+            } else {
+                let next = kept.get(i + 1).map_or(0, |&n| n << (32 - bits));
+                mag.push((d >> bits) | next);
+            }
+        }
+        if bits != 0 {
+            lost |= kept[0] & ((1 << bits) - 1) != 0;
+        }
+        let result = BigInt::new(self.negative, mag);
+        if self.negative && lost {
+            &result - &BigInt::from(1i64)
+        } else {
+            result
+        }
+    }
+}
+
+impl From<i128> for BigInt {
+    fn from(n: i128) -> Self {
+        let mut mag = n.unsigned_abs();
+        let mut digits = Vec::new();
+        while mag > 0 {
+            digits.push(mag as u32);
+            mag >>= 32;
+        }
+        BigInt::new(n < 0, digits)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        BigInt::from(n as i128)
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    result.push(carry as u32);
+    result
+}
+
+/// Requires `a >= b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &d) in a.iter().enumerate() {
+        let mut diff = d as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = (diff < 0) as i64;
+        diff += borrow << 32;
+        result.push(diff as u32);
+    }
+    result
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let cur = result[i + j] as u64 + x as u64 * y as u64 + carry;
+            result[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    result
+}
+
+fn div_rem_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut q = vec![0u32; a.len()];
+    let mut rem = 0u64;
+    for i in (0..a.len()).rev() {
+        let cur = (rem << 32) | a[i] as u64;
+        q[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    (q, rem as u32)
+}
+
+/// Schoolbook binary long division, good enough for the occasional big
+/// number in a puzzle.
+fn div_rem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if b.len() == 1 {
+        let (q, r) = div_rem_small(a, b[0]);
+        return (q, vec![r]);
+    }
+    let mut q = vec![0u32; a.len()];
+    let mut r: Vec<u32> = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        // r = r << 1 | bit i of a
+        let mut carry = (a[i / 32] >> (i % 32)) & 1;
+        for d in r.iter_mut() {
+            let next = *d >> 31;
+            *d = (*d << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            r.push(carry);
+        }
+        if cmp_mag(&r, b) != Ordering::Less {
+            r = sub_mag(&r, b);
+            while r.last() == Some(&0) {
+                r.pop();
+            }
+            q[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (q, r)
+}
+
+impl std::ops::Neg for &BigInt {
+    type Output = BigInt;
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.mag.clone())
+    }
+}
+
+impl std::ops::Add for &BigInt {
+    type Output = BigInt;
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::new(other.negative, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::new(self.negative, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+}
+
+impl std::ops::Sub for &BigInt {
+    type Output = BigInt;
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl std::ops::Mul for &BigInt {
+    type Output = BigInt;
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(
+            self.negative != other.negative,
+            mul_mag(&self.mag, &other.mag),
+        )
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Peel off 9 decimal digits at a time.
+        let mut chunks = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (q, r) = div_rem_small(&mag, 1_000_000_000);
+            chunks.push(r);
+            mag = BigInt::new(false, q).mag;
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        match chunks.pop() {
+            Some(first) => write!(f, "{first}")?,
+            None => write!(f, "0")?,
+        }
+        for chunk in chunks.iter().rev() {
+            write!(f, "{chunk:09}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let n = digits.bytes().fold(BigInt::from(0i64), |acc, c| {
+            &(&acc * &BigInt::from(10i64)) + &BigInt::from((c - b'0') as i64)
+        });
+        if negative {
+            -&n
+        } else {
+            n
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        let (q, r) = b.div_rem(&a).unwrap();
+        assert_eq!(
+            (q.to_string(), r.to_string()),
+            ("-8".into(), "-9000000000900000000090".into())
+        );
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(
+            (&BigInt::from(i64::MAX) + &BigInt::from(1i64)).to_i64(),
+            None
+        );
+        assert!(b < a && BigInt::from(0i64) < a && b < BigInt::from(-1i64));
+        assert!(a.div_rem(&BigInt::from(0i64)).is_none());
+    }
+
+    #[test]
+    fn shifts() {
+        let a = big("123456789012345678901234567890");
+        assert_eq!(a.shl(0), a);
+        assert_eq!(a.shl(37), &a * &big("137438953472"));
+        assert_eq!(a.shl(70).shr(70), a);
+        assert_eq!(a.shr(33), a.div_floor(&big("8589934592")).unwrap());
+        assert_eq!((-&a).shr(33), (-&a).div_floor(&big("8589934592")).unwrap());
+        assert_eq!((-&a).shl(64).shr(64), -&a);
+        assert_eq!(a.shr(1000), big("0"));
+        assert_eq!((-&a).shr(1000), big("-1"));
+        assert_eq!(big("-1").shr(1), big("-1"));
+        // Shifting out every digit, but not by a multiple of 32 bits.
+        let b = big("9223372036854775808");
+        assert_eq!(b.shr(65), big("0"));
+        assert_eq!((-&b).shr(65), big("-1"));
+        assert_eq!(a.shr(128 + 31), big("0"));
+        assert_eq!((-&a).shr(128 + 31), big("-1"));
+    }
+
+    #[test]
+    fn rem_floor() {
+        let a = big("123456789012345678901234567890");
+        let b = big("1000000000000");
+        assert_eq!(a.rem_floor(&b).unwrap(), big("901234567890"));
+        assert_eq!((-&a).rem_floor(&b).unwrap(), big("98765432110"));
+        assert_eq!(a.rem_floor(&-&b).unwrap(), big("-98765432110"));
+        assert_eq!((-&a).rem_floor(&-&b).unwrap(), big("-901234567890"));
+        assert_eq!((-&b).rem_floor(&b).unwrap(), big("0"));
+        assert_eq!(a.rem_floor(&big("0")), None);
+    }
+}
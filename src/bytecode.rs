@@ -1,3 +1,5 @@
+use std::mem::discriminant;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operation {
     Nil,
@@ -45,4 +47,223 @@ pub enum Operation {
     VecUnpack(u8),
     ObjCollect(u8),
     FnCall(u8),
+    /// Turns the value on top of the stack into an iterator.
+    IterStart,
+    /// Pushes the next value of the iterator below the loop value, or jumps
+    /// forward when it's exhausted.
+    IterNext(u8),
+    /// Replaces the arguments of `range` with a lazy iterator over them, so
+    /// loops don't build the whole vector.
+    IterRange(u8),
+    /// Until the matching `TryEnd`, runtime errors jump forward to the catch
+    /// handler with the error on the stack.
+    TryBegin(u8),
+    TryEnd,
+    FloorDiv,
+    XOr,
+    BitAnd,
+    BitOr,
+}
+
+/// Every operation in the order of its serialized tag. New operations have to
+/// be appended so that the tags of existing ones don't change.
+const ENCODING: &[fn(u8) -> Operation] = &[
+    |_| Operation::Nil,
+    Operation::Constant,
+    Operation::Clone,
+    Operation::Swap,
+    Operation::GetVar,
+    Operation::SetVar,
+    |_| Operation::Add,
+    |_| Operation::Sub,
+    |_| Operation::Mul,
+    |_| Operation::Div,
+    |_| Operation::Mod,
+    |_| Operation::LeftShift,
+    |_| Operation::RightShift,
+    |_| Operation::Negate,
+    |_| Operation::UnaryPlus,
+    Operation::Print,
+    |_| Operation::Read,
+    |_| Operation::Not,
+    |_| Operation::And,
+    |_| Operation::Or,
+    |_| Operation::Eq,
+    |_| Operation::Neq,
+    |_| Operation::Lt,
+    |_| Operation::Leq,
+    |_| Operation::Gt,
+    |_| Operation::Geq,
+    |_| Operation::Pop,
+    |_| Operation::Return,
+    Operation::Jump,
+    Operation::JumpBack,
+    Operation::JumpIf,
+    |_| Operation::Noop,
+    |_| Operation::VecGet,
+    |_| Operation::VecSlice,
+    |_| Operation::VecSet,
+    Operation::VecCollect,
+    Operation::VecUnpack,
+    Operation::ObjCollect,
+    Operation::FnCall,
+    |_| Operation::IterStart,
+    Operation::IterNext,
+    Operation::TryBegin,
+    |_| Operation::TryEnd,
+    |_| Operation::FloorDiv,
+    |_| Operation::XOr,
+    |_| Operation::BitAnd,
+    |_| Operation::BitOr,
+    Operation::IterRange,
+];
+
+impl Operation {
+    /// Encodes the operation as a tag and its operand.
+    pub fn encode(&self) -> [u8; 2] {
+        let operand = self.operand().unwrap_or(0);
+        let tag = ENCODING
+            .iter()
+            .position(|op| discriminant(&op(operand)) == discriminant(self))
+            .expect("Every operation has an encoding");
+        [tag as u8, operand]
+    }
+
+    pub fn decode(tag: u8, operand: u8) -> Option<Self> {
+        ENCODING.get(tag as usize).map(|op| op(operand))
+    }
+
+    pub fn operand(&self) -> Option<u8> {
+        match self {
+            Operation::Constant(n)
+            | Operation::Clone(n)
+            | Operation::Swap(n)
+            | Operation::GetVar(n)
+            | Operation::SetVar(n)
+            | Operation::Print(n)
+            | Operation::Jump(n)
+            | Operation::JumpBack(n)
+            | Operation::JumpIf(n)
+            | Operation::VecCollect(n)
+            | Operation::VecUnpack(n)
+            | Operation::ObjCollect(n)
+            | Operation::FnCall(n)
+            | Operation::IterNext(n)
+            | Operation::IterRange(n)
+            | Operation::TryBegin(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// How many values the operation leaves on the stack compared to before
+    /// it ran. Jumps are counted as if they were not taken.
+    pub fn stack_effect(&self) -> isize {
+        match self {
+            Operation::Nil
+            | Operation::Constant(_)
+            | Operation::Clone(_)
+            | Operation::GetVar(_)
+            | Operation::IterNext(_)
+            | Operation::Read => 1,
+            Operation::Swap(_)
+            | Operation::SetVar(_)
+            | Operation::Negate
+            | Operation::UnaryPlus
+            | Operation::Not
+            | Operation::Return
+            | Operation::Jump(_)
+            | Operation::JumpBack(_)
+            | Operation::IterStart
+            | Operation::TryBegin(_)
+            | Operation::TryEnd
+            | Operation::Noop => 0,
+            Operation::Add
+            | Operation::Sub
+            | Operation::Mul
+            | Operation::Div
+            | Operation::Mod
+            | Operation::FloorDiv
+            | Operation::LeftShift
+            | Operation::RightShift
+            | Operation::And
+            | Operation::Or
+            | Operation::XOr
+            | Operation::BitAnd
+            | Operation::BitOr
+            | Operation::Eq
+            | Operation::Neq
+            | Operation::Lt
+            | Operation::Leq
+            | Operation::Gt
+            | Operation::Geq
+            | Operation::Pop
+            | Operation::JumpIf(_)
+            | Operation::VecGet => -1,
+            Operation::VecSlice | Operation::VecSet => -2,
+            Operation::Print(n) | Operation::VecCollect(n) | Operation::IterRange(n) => {
+                1 - *n as isize
+            }
+            Operation::VecUnpack(n) => *n as isize,
+            Operation::ObjCollect(n) => 1 - 2 * *n as isize,
+            Operation::FnCall(n) => -(*n as isize),
+        }
+    }
+
+    /// How many values the operation reads from the top of the stack, which
+    /// have to be there before it runs.
+    pub fn stack_args(&self) -> usize {
+        match self {
+            Operation::Nil
+            | Operation::Constant(_)
+            | Operation::GetVar(_)
+            | Operation::Read
+            | Operation::Jump(_)
+            | Operation::JumpBack(_)
+            | Operation::TryBegin(_)
+            | Operation::TryEnd
+            | Operation::Noop => 0,
+            Operation::SetVar(_)
+            | Operation::Negate
+            | Operation::UnaryPlus
+            | Operation::Not
+            | Operation::Pop
+            | Operation::Return
+            | Operation::JumpIf(_)
+            | Operation::IterStart
+            | Operation::VecUnpack(_) => 1,
+            Operation::Add
+            | Operation::Sub
+            | Operation::Mul
+            | Operation::Div
+            | Operation::Mod
+            | Operation::FloorDiv
+            | Operation::LeftShift
+            | Operation::RightShift
+            | Operation::And
+            | Operation::Or
+            | Operation::XOr
+            | Operation::BitAnd
+            | Operation::BitOr
+            | Operation::Eq
+            | Operation::Neq
+            | Operation::Lt
+            | Operation::Leq
+            | Operation::Gt
+            | Operation::Geq
+            | Operation::VecGet
+            | Operation::IterNext(_) => 2,
+            Operation::VecSlice | Operation::VecSet => 3,
+            Operation::Print(n) | Operation::VecCollect(n) | Operation::IterRange(n) => *n as usize,
+            Operation::ObjCollect(n) => 2 * *n as usize,
+            Operation::Clone(n) | Operation::Swap(n) | Operation::FnCall(n) => *n as usize + 1,
+        }
+    }
+
+    pub fn mnemonic(&self) -> String {
+        let name = format!("{self:?}");
+        match name.split_once('(') {
+            Some((name, _)) => name.to_string(),
+            None => name,
+        }
+    }
 }
@@ -0,0 +1,90 @@
+//! An on-disk cache of compiled chunks, keyed by a hash of the source code.
+use std::{
+    cell::Cell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    rc::Rc,
+};
+
+use crate::{
+    runtime::{Chunk, Value},
+    serialize::VERSION,
+};
+
+pub struct Cache {
+    dir: PathBuf,
+    hits: Cell<usize>,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            hits: Cell::new(0),
+        }
+    }
+
+    /// Uses the `AOC_CACHE_DIR` environment variable, falling back to a
+    /// folder in the system's temporary directory.
+    pub fn default_dir() -> PathBuf {
+        match std::env::var_os("AOC_CACHE_DIR") {
+            Some(dir) => dir.into(),
+            None => std::env::temp_dir().join("aoc-lang-cache"),
+        }
+    }
+
+    /// How many chunks were loaded from the cache instead of being compiled.
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+
+    /// Strict mode compiles the same code differently, so it gets its own
+    /// entry.
+    fn path(&self, source_name: Option<&Rc<str>>, code: &str, strict: bool) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        (VERSION, source_name, code, strict).hash(&mut hasher);
+        self.dir.join(format!("{:016x}.aocb", hasher.finish()))
+    }
+
+    pub fn load(&self, source_name: Option<&Rc<str>>, code: &str, strict: bool) -> Option<Chunk> {
+        let bytes = std::fs::read(self.path(source_name, code, strict)).ok()?;
+        let mut chunk = Chunk::from_bytes(&bytes).ok()?;
+        chunk.strict = strict;
+        // Guards against hash collisions.
+        if &*chunk.code != code || chunk.source_name.as_ref() != source_name {
+            return None;
+        }
+        self.hits.set(self.hits.get() + 1);
+        Some(chunk)
+    }
+
+    /// Stores the chunk, unless it imports other files, because then the
+    /// cached version would go stale when only the imported file changes.
+    /// Chunks with warnings aren't stored either, so that the warnings are
+    /// printed on every run instead of only the first one.
+    /// Failing to write the cache is not an error, the program just gets
+    /// compiled again next time.
+    pub fn store(&self, chunk: &Chunk) {
+        if has_imports(chunk, chunk) || !chunk.warnings.borrow().is_empty() {
+            return;
+        }
+        let Ok(bytes) = chunk.to_bytes() else {
+            return;
+        };
+        let path = self.path(chunk.source_name.as_ref(), &chunk.code, chunk.strict);
+        let _ = std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(path, bytes));
+    }
+}
+
+/// Imported files are compiled into chunks with their own source code.
+fn has_imports(root: &Chunk, chunk: &Chunk) -> bool {
+    chunk.constants.iter().any(|c| match c {
+        Value::Fn { chunk: f, .. } => {
+            !Rc::ptr_eq(&f.code, &root.code)
+                || f.source_name != root.source_name
+                || has_imports(root, f)
+        }
+        _ => false,
+    })
+}
@@ -1,11 +1,18 @@
 use std::fmt::Display;
+use std::rc::Rc;
 
 use crate::token::{Pos, Snippet};
 
 pub type Result<T, E> = std::result::Result<T, Error<E>>;
 
 pub trait Stackable {
-    fn stack(self, pos: Pos, code: &str) -> Self;
+    fn stack_in(self, source: Option<&Rc<str>>, pos: Pos, code: &str) -> Self;
+    fn stack(self, pos: Pos, code: &str) -> Self
+    where
+        Self: Sized,
+    {
+        self.stack_in(None, pos, code)
+    }
     fn context(self, context: &str) -> Self;
     fn wrap(self, context: &str, pos: Pos, code: &str) -> Self
     where
@@ -16,8 +23,8 @@ pub trait Stackable {
 }
 
 impl<T, E: Stackable> Stackable for std::result::Result<T, E> {
-    fn stack(self, pos: Pos, code: &str) -> Self {
-        self.map_err(|e| e.stack(pos, code))
+    fn stack_in(self, source: Option<&Rc<str>>, pos: Pos, code: &str) -> Self {
+        self.map_err(|e| e.stack_in(source, pos, code))
     }
     fn context(self, context: &str) -> Self {
         self.map_err(|e| e.context(context))
@@ -35,11 +42,53 @@ impl Kind for RuntimeError {}
 #[derive(Debug, Default)]
 pub struct ParserError;
 impl Kind for ParserError {}
+#[derive(Debug, Default)]
+pub struct BytecodeError;
+impl Kind for BytecodeError {}
+/// Problems found while compiling that don't stop the program from running.
+#[derive(Debug, Default)]
+pub struct Warning;
+impl Kind for Warning {}
+
+/// What went wrong in a runtime error, so that `catch` handlers can tell
+/// errors apart.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorType {
+    /// A value of the wrong type, e.g. adding a number to a function.
+    Type,
+    /// A value of the right type that is not allowed, e.g. a zero range step.
+    Value,
+    Index,
+    ZeroDivision,
+    /// A function called with the wrong number of arguments.
+    Call,
+    Assert,
+    /// Raised by the program with `error(msg)`.
+    User,
+    #[default]
+    Other,
+}
+
+impl ErrorType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorType::Type => "type",
+            ErrorType::Value => "value",
+            ErrorType::Index => "index",
+            ErrorType::ZeroDivision => "zero_division",
+            ErrorType::Call => "call",
+            ErrorType::Assert => "assert",
+            ErrorType::User => "user",
+            ErrorType::Other => "other",
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Error<E: Kind> {
     underlying: Option<Box<dyn std::error::Error>>,
     kind: E,
+    ty: ErrorType,
     context: String,
     stack: Vec<Snippet>,
 }
@@ -49,6 +98,7 @@ impl<E: Kind> Error<E> {
         Self {
             underlying: Some(Box::new(e)),
             kind: E::default(),
+            ty: ErrorType::default(),
             context: String::new(),
             stack: Vec::new(),
         }
@@ -57,6 +107,7 @@ impl<E: Kind> Error<E> {
         Self {
             underlying: None,
             kind: E::default(),
+            ty: ErrorType::default(),
             context,
             stack: Vec::new(),
         }
@@ -66,23 +117,46 @@ impl<E: Kind> Error<E> {
         Self {
             underlying: None,
             kind: E::default(),
+            ty: ErrorType::default(),
             context,
             stack: vec![pos.extract(code)],
         }
     }
 
+    pub fn typed(ty: ErrorType, context: String) -> Self {
+        Self {
+            ty,
+            ..Self::new(context)
+        }
+    }
+
+    pub fn error_type(&self) -> ErrorType {
+        self.ty
+    }
+
+    /// The error message without its kind and stack trace.
+    pub fn message(&self) -> String {
+        match &self.underlying {
+            Some(e) if self.context.is_empty() => e.to_string(),
+            Some(e) => format!("{}: {e}", self.context),
+            None => self.context.clone(),
+        }
+    }
+
     pub fn stack_trace(&self) -> String {
         self.stack
             .iter()
             .map(
                 |Snippet {
+                     source,
                      line,
                      col,
                      line_prefix,
                      snippet,
                      line_suffix,
                  }| {
-                    format!("on line {line}:{col}: {line_prefix}\x1b[91m\x1b[1m{snippet}\x1b[0m{line_suffix}")
+                    let source = source.as_ref().map(|s| format!("in {s} ")).unwrap_or_default();
+                    format!("{source}on line {line}:{col}: {line_prefix}\x1b[91m\x1b[1m{snippet}\x1b[0m{line_suffix}")
                 },
             )
             .collect::<Vec<_>>()
@@ -90,8 +164,10 @@ impl<E: Kind> Error<E> {
     }
 }
 impl<T: Kind> Stackable for Error<T> {
-    fn stack(mut self, pos: Pos, code: &str) -> Self {
-        self.stack.push(pos.extract(code));
+    fn stack_in(mut self, source: Option<&Rc<str>>, pos: Pos, code: &str) -> Self {
+        let mut snippet = pos.extract(code);
+        snippet.source = source.cloned();
+        self.stack.push(snippet);
         self
     }
     fn context(mut self, context: &str) -> Self {
@@ -1,8 +1,10 @@
 use std::collections::HashSet;
 use std::rc::Rc;
 
+use crate::bigint::BigInt;
 use crate::bytecode::Operation;
 use crate::error::Stackable;
+use crate::native::Native;
 use crate::runtime::{Chunk, Value};
 use crate::token::Pos;
 use crate::{lexer, parser};
@@ -16,6 +18,7 @@ pub enum Operator {
     Sub,
     Mul,
     Div,
+    FloorDiv,
     Mod,
     XOr,
 
@@ -31,6 +34,8 @@ pub enum Operator {
 
     LeftShift,
     RightShift,
+    BitAnd,
+    BitOr,
 }
 impl Operator {
     pub fn all_bin() -> Vec<HashSet<Operator>> {
@@ -48,12 +53,20 @@ impl Operator {
                 Operator::Eq,
                 Operator::Neq,
             ]),
+            // Bitwise
+            HashSet::from([Operator::BitOr]),
+            HashSet::from([Operator::BitAnd]),
             // Bitshift
             HashSet::from([Operator::LeftShift, Operator::RightShift]),
             // Sum
             HashSet::from([Operator::Add, Operator::Sub]),
             // Mul
-            HashSet::from([Operator::Mul, Operator::Div, Operator::Mod]),
+            HashSet::from([
+                Operator::Mul,
+                Operator::Div,
+                Operator::FloorDiv,
+                Operator::Mod,
+            ]),
         ]
     }
 
@@ -63,9 +76,11 @@ impl Operator {
             Operator::Sub => Operation::Sub,
             Operator::Mul => Operation::Mul,
             Operator::Div => Operation::Div,
+            Operator::FloorDiv => Operation::FloorDiv,
             Operator::Mod => Operation::Mod,
             Operator::And => Operation::And,
             Operator::Or => Operation::Or,
+            Operator::XOr => Operation::XOr,
             Operator::Eq => Operation::Eq,
             Operator::Neq => Operation::Neq,
             Operator::Less => Operation::Lt,
@@ -74,6 +89,8 @@ impl Operator {
             Operator::GreaterEq => Operation::Geq,
             Operator::LeftShift => Operation::LeftShift,
             Operator::RightShift => Operation::RightShift,
+            Operator::BitAnd => Operation::BitAnd,
+            Operator::BitOr => Operation::BitOr,
             _ => return None,
         })
     }
@@ -83,10 +100,12 @@ impl Operator {
 pub enum ExprType {
     // Literals
     Int(i64),
+    BigInt(Rc<BigInt>),
     Float(f64),
     Str(Rc<String>),
     Identifier(String),
     Nil,
+    Bool(bool),
     // Operations
     BinaryOp {
         op: Operator,
@@ -108,7 +127,10 @@ pub enum ExprType {
         right: Box<Expr>,
     },
     // Scope
+    /// Variables first assigned inside a block are not visible after it.
     Block(Vec<Expr>),
+    /// The top level of a file, whose variables stay visible to imports.
+    Program(Vec<Expr>),
     // IO
     Print(Vec<Expr>),
     Read,
@@ -121,6 +143,36 @@ pub enum ExprType {
     While {
         cond: Box<Expr>,
         body: Box<Expr>,
+        /// The step of a C style `for` loop, which also runs after `continue`.
+        step: Option<Box<Expr>>,
+        elsebody: Option<Box<Expr>>,
+        label: Option<String>,
+    },
+    DoWhile {
+        body: Box<Expr>,
+        cond: Box<Expr>,
+        label: Option<String>,
+    },
+    ForIn {
+        var: Box<Expr>,
+        iter: Box<Expr>,
+        body: Box<Expr>,
+        elsebody: Option<Box<Expr>>,
+        label: Option<String>,
+    },
+    Times {
+        count: Box<Expr>,
+        body: Box<Expr>,
+        label: Option<String>,
+    },
+    Break(Option<String>),
+    Continue(Option<String>),
+    /// `try body catch var handler` runs the handler with the error in `var`
+    /// if the body fails.
+    Try {
+        body: Box<Expr>,
+        var: String,
+        handler: Box<Expr>,
     },
     // Functions
     FnDef {
@@ -138,7 +190,11 @@ pub enum ExprType {
         idx: Vec<Expr>,
     },
     ObjectDef(Vec<(Expr, Expr)>),
-    Use(String),
+    Use {
+        filename: String,
+        /// Variables to copy out of the imported file with `use "file" { a, b }`.
+        names: Option<Vec<String>>,
+    },
     Return(Box<Expr>),
 }
 
@@ -159,7 +215,9 @@ impl Expr {
             ExprType::Nil => {
                 chunk.push_op(Operation::Nil, self.pos);
             }
+            ExprType::Bool(v) => self.constant(&mut chunk, Value::Bool(*v))?,
             ExprType::Int(v) => self.constant(&mut chunk, Value::Int(*v))?,
+            ExprType::BigInt(v) => self.constant(&mut chunk, Value::BigInt(v.clone()))?,
             ExprType::Float(v) => self.constant(&mut chunk, Value::Float(*v))?,
             ExprType::Str(v) => self.constant(&mut chunk, Value::Str(v.clone()))?,
             ExprType::BinaryOp { op, left, right } => {
@@ -185,17 +243,11 @@ impl Expr {
                 );
             }
             ExprType::Block(exprs) => {
-                if exprs.is_empty() {
-                    chunk.push_op(Operation::Nil, self.pos);
-                }
-
-                for (i, expr) in exprs.iter().enumerate() {
-                    if i > 0 {
-                        chunk.push_op(Operation::Pop, self.pos);
-                    }
-                    chunk = expr.to_chunk(chunk)?;
-                }
+                chunk.begin_scope();
+                chunk = self.sequence(chunk, exprs)?;
+                chunk.end_scope();
             }
+            ExprType::Program(exprs) => chunk = self.sequence(chunk, exprs)?,
             ExprType::Print(exprs) => {
                 for expr in exprs {
                     chunk = expr.to_chunk(chunk)?;
@@ -204,6 +256,11 @@ impl Expr {
                     Operation::Print(self.to_u8(exprs.len(), "Printing more than 255 values")?),
                     self.pos,
                 );
+                // So that a function ending with a print doesn't return what it printed
+                if chunk.strict {
+                    chunk.push_op(Operation::Pop, self.pos);
+                    chunk.push_op(Operation::Nil, self.pos);
+                }
             }
             ExprType::If {
                 cond,
@@ -212,9 +269,11 @@ impl Expr {
             } => {
                 chunk = cond.to_chunk(chunk)?;
                 let jump_if_idx = chunk.push_op(Operation::JumpIf(0), self.pos);
+                let depth = chunk.stack_depth();
                 chunk = body.to_chunk(chunk)?;
                 let jump_idx = chunk.push_op(Operation::Jump(0), self.pos);
                 chunk.jump_from(jump_if_idx)?;
+                chunk.set_stack_depth(depth);
                 if let Some(elsebody) = elsebody {
                     chunk = elsebody.to_chunk(chunk)?;
                 } else {
@@ -222,13 +281,25 @@ impl Expr {
                 }
                 chunk.jump_from(jump_idx)?;
             }
-            ExprType::While { cond, body } => {
+            ExprType::While {
+                cond,
+                body,
+                step,
+                elsebody,
+                label,
+            } => {
                 chunk.push_op(Operation::Nil, self.pos);
                 let start_idx = chunk.num_bytecode();
                 chunk = cond.to_chunk(chunk)?;
                 let jump_if_idx = chunk.push_op(Operation::JumpIf(0), self.pos);
                 chunk.push_op(Operation::Pop, self.pos);
+                chunk.begin_loop(label.clone());
                 chunk = body.to_chunk(chunk)?;
+                chunk.continue_here()?;
+                if let Some(step) = step {
+                    chunk.push_op(Operation::Pop, self.pos);
+                    chunk = step.to_chunk(chunk)?;
+                }
                 chunk.push_op(
                     Operation::JumpBack(
                         (chunk.num_bytecode() + 1usize - start_idx)
@@ -239,9 +310,117 @@ impl Expr {
                     self.pos,
                 );
                 chunk.jump_from(jump_if_idx)?;
+                // Only runs when the loop finishes without a break
+                if let Some(elsebody) = elsebody {
+                    chunk.push_op(Operation::Pop, self.pos);
+                    chunk = elsebody.to_chunk(chunk)?;
+                }
+                chunk.end_loop()?;
+            }
+            ExprType::DoWhile { body, cond, label } => {
+                let start_idx = chunk.num_bytecode();
+                chunk.begin_loop(label.clone());
+                chunk = body.to_chunk(chunk)?;
+                chunk.continue_here()?;
+                let depth = chunk.stack_depth();
+                chunk = cond.to_chunk(chunk)?;
+                let jump_if_idx = chunk.push_op(Operation::JumpIf(0), self.pos);
+                chunk.push_op(Operation::Pop, self.pos);
+                chunk.push_op(
+                    Operation::JumpBack(
+                        (chunk.num_bytecode() + 1usize - start_idx)
+                            .try_into()
+                            .map_err(Error::from)
+                            .wrap("Loop body longer than 255 bytecode", self.pos, &self.code)?,
+                    ),
+                    self.pos,
+                );
+                chunk.jump_from(jump_if_idx)?;
+                chunk.set_stack_depth(depth);
+                chunk.end_loop()?;
+            }
+            ExprType::ForIn {
+                var,
+                iter,
+                body,
+                elsebody,
+                label,
+            } => {
+                chunk = match &iter.kind {
+                    // Counting with `range` doesn't need to build its vector.
+                    ExprType::FnCall { func, args }
+                        if (1..=3).contains(&args.len())
+                            && matches!(&func.kind, ExprType::Identifier(name) if name == "range")
+                            && chunk.lookup_var("range", false).is_none() =>
+                    {
+                        for arg in args {
+                            chunk = arg.to_chunk(chunk)?;
+                        }
+                        chunk.push_op(Operation::IterRange(args.len() as u8), iter.pos);
+                        chunk
+                    }
+                    _ => iter.to_chunk(chunk)?,
+                };
+                chunk = self.iterate(chunk, Some(var), body, elsebody.as_deref(), label)?;
+            }
+            ExprType::Times { count, body, label } => {
+                // `times n body` is `for _ in range(n) body` where the range
+                // iterator is the hidden counter.
+                chunk = count.to_chunk(chunk)?;
+                chunk.push_op(Operation::IterRange(1), self.pos);
+                chunk = self.iterate(chunk, None, body, None, label)?;
+            }
+            ExprType::Break(label) => chunk.break_loop(label.as_deref(), self.pos)?,
+            ExprType::Continue(label) => chunk.continue_loop(label.as_deref(), self.pos)?,
+            ExprType::Try { body, var, handler } => {
+                let depth = chunk.stack_depth();
+                let try_idx = chunk.push_op(Operation::TryBegin(0), self.pos);
+                chunk.begin_try();
+                chunk = body.to_chunk(chunk)?;
+                chunk.end_try();
+                chunk.push_op(Operation::TryEnd, self.pos);
+                let end_idx = chunk.push_op(Operation::Jump(0), self.pos);
+                chunk.jump_from(try_idx)?;
+                // The error replaces whatever the body left behind.
+                chunk.set_stack_depth(depth + 1);
+                chunk.begin_scope();
+                let idx = chunk.shadow_var(var);
+                chunk.push_op(
+                    Operation::SetVar(self.to_u8(idx, "More than 255 variables in local scope")?),
+                    self.pos,
+                );
+                chunk.push_op(Operation::Pop, self.pos);
+                chunk = handler.to_chunk(chunk)?;
+                chunk.end_scope();
+                chunk.jump_from(end_idx)?;
+            }
+            ExprType::Define { var, val } => {
+                if chunk.declared_in_scope(var) {
+                    let msg = format!("let {var} shadows a variable in the same scope");
+                    if chunk.strict {
+                        return Err(self.err(msg));
+                    }
+                    let warning = crate::error::Error::build(msg, self.pos, &self.code);
+                    chunk.warnings.borrow_mut().push(warning);
+                }
+                // Functions see the new variable so they can call themselves,
+                // other values still see the one being shadowed.
+                let idx = if matches!(val.kind, ExprType::FnDef { .. }) {
+                    let idx = chunk.shadow_var(var);
+                    chunk = val.to_chunk(chunk)?;
+                    idx
+                } else {
+                    chunk = val.to_chunk(chunk)?;
+                    chunk.shadow_var(var)
+                };
+                chunk.push_op(
+                    Operation::SetVar(self.to_u8(idx, "More than 255 variables in local scope")?),
+                    self.pos,
+                );
             }
             ExprType::Assign { left, right } => {
                 if let ExprType::Identifier(var) = &left.kind {
+                    left.check_declared(&mut chunk, var)?;
                     chunk.get_var(var); // Initialize variable for recursion
                 }
                 chunk = right.to_chunk(chunk)?;
@@ -293,18 +472,24 @@ impl Expr {
                 }
             },
 
-            ExprType::Identifier(var) => {
-                let idx = chunk
-                    .lookup_var(var, false)
-                    .ok_or_else(|| self.err(format!("Unknown variable {var}")))?;
-                chunk.push_op(
-                    Operation::GetVar(self.to_u8(idx, "More than 255 variables in local scope")?),
-                    self.pos,
-                );
-            }
+            ExprType::Identifier(var) => match chunk.lookup_var(var, false) {
+                Some(idx) => {
+                    chunk.push_op(
+                        Operation::GetVar(
+                            self.to_u8(idx, "More than 255 variables in local scope")?,
+                        ),
+                        self.pos,
+                    );
+                }
+                None => {
+                    let native = Native::from_name(var)
+                        .ok_or_else(|| self.err(format!("Unknown variable {var}")))?;
+                    self.constant(&mut chunk, Value::Native(native))?;
+                }
+            },
 
             ExprType::VecDef(exprs) => {
-                for expr in exprs.iter().rev() {
+                for expr in exprs {
                     chunk = expr.to_chunk(chunk)?;
                 }
                 chunk.push_op(
@@ -343,6 +528,7 @@ impl Expr {
                 chunk = f
                     .take_parent()
                     .expect("I just added the parent, now I'm taking it back.");
+                f.finish();
                 let f = Value::Fn {
                     num_params: args.len(),
                     captured: Vec::new(),
@@ -378,14 +564,12 @@ impl Expr {
                 chunk = expr.to_chunk(chunk)?;
                 chunk.push_op(Operation::Return, self.pos);
             }
-            ExprType::Use(filename) => {
-                let code = std::fs::read_to_string(filename)
-                    .map_err(Error::from)
-                    .wrap(
-                        &format!("cannot open imported file {filename}"),
-                        self.pos,
-                        &self.code,
-                    )?;
+            ExprType::Use { filename, names } => {
+                let code = read_import(filename).map_err(Error::from).wrap(
+                    &format!("cannot open imported file {filename}"),
+                    self.pos,
+                    &self.code,
+                )?;
                 let tokens = lexer::Lexer::new(Rc::from(code));
                 let expr = parser::Parser::new(tokens)
                     .parse()
@@ -395,11 +579,24 @@ impl Expr {
                         self.pos,
                         &self.code,
                     )?;
-                let use_chunk = expr.to_chunk(expr.code.clone().into()).wrap(
+                let mut use_chunk = Chunk::named(expr.code.clone(), Some(filename.as_str().into()));
+                use_chunk.strings = chunk.strings.clone();
+                use_chunk.strict = chunk.strict;
+                use_chunk.warnings = chunk.warnings.clone();
+                let mut use_chunk = expr.to_chunk(use_chunk).wrap(
                     &format!("could not compile imported file {filename}"),
                     self.pos,
                     &self.code,
                 )?;
+                for name in names.iter().flatten() {
+                    if !use_chunk.var_names.contains(name) || !is_exported(name) {
+                        return Err(
+                            self.err(format!("Imported file {filename} doesn't export {name}"))
+                        );
+                    }
+                }
+                expr.export(&mut use_chunk)?;
+                use_chunk.finish();
                 let f = Value::Fn {
                     num_params: 0,
                     captured: Vec::new(),
@@ -407,16 +604,114 @@ impl Expr {
                 };
                 self.constant(&mut chunk, f)?;
                 chunk.push_op(Operation::FnCall(0), self.pos);
+                for name in names.iter().flatten() {
+                    self.constant(&mut chunk, Value::Str(Rc::new(name.clone())))?;
+                    chunk.push_op(Operation::Clone(1), self.pos);
+                    chunk.push_op(Operation::VecGet, self.pos);
+                    let idx = chunk.get_var(name);
+                    chunk.push_op(
+                        Operation::SetVar(
+                            self.to_u8(idx, "More than 255 variables in local scope")?,
+                        ),
+                        self.pos,
+                    );
+                    chunk.push_op(Operation::Pop, self.pos);
+                }
             }
-            ex => return Err(self.err(format!("Unimplemented expression {ex:?}"))),
         }
 
         Ok(chunk)
     }
 
+    /// Evaluates the expressions in order, leaving only the last value.
+    fn sequence(&self, mut chunk: Chunk, exprs: &[Expr]) -> Result<Chunk> {
+        if exprs.is_empty() {
+            chunk.push_op(Operation::Nil, self.pos);
+        }
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                chunk.push_op(Operation::Pop, self.pos);
+            }
+            chunk = expr.to_chunk(chunk)?;
+        }
+        Ok(chunk)
+    }
+
+    /// Makes an imported file return an object with its exported top level
+    /// variables instead of its last value.
+    fn export(&self, chunk: &mut Chunk) -> Result<()> {
+        chunk.push_op(Operation::Pop, self.pos);
+        let names = chunk.var_names.clone();
+        let mut num_exported = 0;
+        for (idx, name) in names.into_iter().enumerate() {
+            // Skip private and shadowed variables
+            if !is_exported(&name) || chunk.lookup_var(&name, false) != Some(idx) {
+                continue;
+            }
+            self.constant(chunk, Value::Str(Rc::new(name)))?;
+            chunk.push_op(
+                Operation::GetVar(self.to_u8(idx, "More than 255 variables in local scope")?),
+                self.pos,
+            );
+            num_exported += 1;
+        }
+        chunk.push_op(
+            Operation::ObjCollect(self.to_u8(num_exported, "More than 255 object fields")?),
+            self.pos,
+        );
+        Ok(())
+    }
+
+    /// Loops over the iterable on top of the stack, assigning every value to
+    /// `var` if there is one.
+    fn iterate(
+        &self,
+        mut chunk: Chunk,
+        var: Option<&Expr>,
+        body: &Expr,
+        elsebody: Option<&Expr>,
+        label: &Option<String>,
+    ) -> Result<Chunk> {
+        // The iterator stays below the loop value until the loop ends.
+        chunk.push_op(Operation::IterStart, self.pos);
+        chunk.push_op(Operation::Nil, self.pos);
+        let start_idx = chunk.num_bytecode();
+        let next_idx = chunk.push_op(Operation::IterNext(0), self.pos);
+        if let Some(var) = var {
+            chunk = var.inner_assign(chunk, self.pos)?;
+        }
+        chunk.push_op(Operation::Pop, self.pos);
+        chunk.push_op(Operation::Pop, self.pos);
+        let depth = chunk.stack_depth();
+        chunk.begin_loop(label.clone());
+        chunk = body.to_chunk(chunk)?;
+        chunk.continue_here()?;
+        chunk.push_op(
+            Operation::JumpBack(
+                (chunk.num_bytecode() + 1usize - start_idx)
+                    .try_into()
+                    .map_err(Error::from)
+                    .wrap("Loop body longer than 255 bytecode", self.pos, &self.code)?,
+            ),
+            self.pos,
+        );
+        chunk.jump_from(next_idx)?;
+        chunk.set_stack_depth(depth + 1);
+        // Only runs when the loop finishes without a break
+        if let Some(elsebody) = elsebody {
+            chunk.push_op(Operation::Pop, self.pos);
+            chunk = elsebody.to_chunk(chunk)?;
+        }
+        chunk.end_loop()?;
+        chunk.push_op(Operation::Swap(1), self.pos);
+        chunk.push_op(Operation::Pop, self.pos);
+        Ok(chunk)
+    }
+
     fn inner_assign(&self, mut chunk: Chunk, pos: Pos) -> Result<Chunk> {
         match &self.kind {
             ExprType::Identifier(var) => {
+                self.check_declared(&mut chunk, var)?;
                 let idx = chunk.get_var(var);
                 chunk.push_op(
                     Operation::SetVar(self.to_u8(idx, "More than 255 variables in local scope")?),
@@ -449,6 +744,16 @@ impl Expr {
         Ok(chunk)
     }
 
+    /// In strict mode variables have to exist before they are assigned to.
+    fn check_declared(&self, chunk: &mut Chunk, var: &str) -> Result<()> {
+        if chunk.strict && chunk.lookup_var(var, false).is_none() {
+            return Err(self.err(format!(
+                "Assignment to undeclared variable {var}, declare it with let"
+            )));
+        }
+        Ok(())
+    }
+
     fn err(&self, msg: String) -> Error {
         Error::new(msg).stack(self.pos, &self.code)
     }
@@ -476,3 +781,32 @@ impl std::fmt::Debug for Expr {
         }
     }
 }
+
+/// The standard library, written in the language itself.
+const STD: &str = include_str!("std.aoc");
+
+/// Environment variable with a directory whose `std.aoc` is imported by
+/// `use "std"` instead of the bundled standard library.
+pub const STD_PATH_VAR: &str = "AOC_STD_PATH";
+
+/// Reads an imported file, or the source of `std`.
+fn read_import(filename: &str) -> std::io::Result<String> {
+    match filename {
+        // There's no environment in WASM, so it always gets the bundled one.
+        "std" => std_source(std::env::var_os(STD_PATH_VAR).as_deref()),
+        _ => std::fs::read_to_string(filename),
+    }
+}
+
+/// Reads `std.aoc` from `dir`, or returns the bundled standard library.
+pub(crate) fn std_source(dir: Option<&std::ffi::OsStr>) -> std::io::Result<String> {
+    match dir {
+        Some(dir) => std::fs::read_to_string(std::path::Path::new(dir).join("std.aoc")),
+        None => Ok(STD.to_string()),
+    }
+}
+
+/// Top level variables starting with `_` stay private to an imported file.
+fn is_exported(name: &str) -> bool {
+    !name.starts_with('_')
+}
@@ -0,0 +1,464 @@
+//! Turns a syntax tree back into source code, formatted the same way no matter
+//! how the original was written. Comments are not part of the tree, so they
+//! are lost.
+use crate::expr::{Expr, ExprType, Operator};
+use crate::token::TokenType;
+
+const INDENT: &str = "    ";
+
+impl Expr {
+    /// Source code that parses back into the same tree.
+    pub fn format(&self) -> String {
+        match &self.kind {
+            // An empty file parses into a lone `nil`.
+            ExprType::Program(exprs) if matches!(exprs.as_slice(), [e] if e.kind == ExprType::Nil) => {
+                String::new()
+            }
+            ExprType::Program(exprs) => exprs.iter().map(|e| expr(e, 0) + "\n").collect(),
+            _ => expr(self, 0),
+        }
+    }
+}
+
+fn expr(e: &Expr, depth: usize) -> String {
+    match &e.kind {
+        ExprType::Int(n) => n.to_string(),
+        ExprType::BigInt(n) => n.to_string(),
+        ExprType::Float(n) => {
+            let s = n.to_string();
+            if s.contains('.') {
+                s
+            } else {
+                s + ".0"
+            }
+        }
+        ExprType::Str(s) => format!("\"{s}\""),
+        ExprType::Identifier(name) => name.clone(),
+        ExprType::Nil => "nil".to_string(),
+        ExprType::Bool(b) => b.to_string(),
+        ExprType::BinaryOp { op, left, right } => {
+            let level = level(op);
+            format!(
+                "{} {} {}",
+                binary_operand(left, depth, level),
+                symbol(op),
+                binary_operand(right, depth, level + 1),
+            )
+        }
+        ExprType::UnaryOp(op, val) => {
+            let val = match val.kind {
+                ExprType::UnaryOp(..) => format!("({})", expr(val, depth)),
+                _ => binary_operand(val, depth, usize::MAX),
+            };
+            format!("{}{val}", symbol(op))
+        }
+        ExprType::Define { var, val } => format!("let {var} = {}", expr(val, depth)),
+        ExprType::Assign { left, right } => {
+            format!("{} = {}", operand(left, depth), expr(right, depth))
+        }
+        ExprType::AssignOp { op, left, right } => format!(
+            "{} {}= {}",
+            operand(left, depth),
+            symbol(op),
+            expr(right, depth)
+        ),
+        ExprType::Block(exprs) => match for_loop(e) {
+            Some((init, cond, step, body, elsebody, label)) => format!(
+                "{}for {}; {}; {}{}",
+                label_prefix(label),
+                expr(init, depth),
+                expr(cond, depth),
+                expr(step, depth),
+                body_and_else(body, elsebody, depth),
+            ),
+            None => block(exprs, depth),
+        },
+        ExprType::Program(exprs) => block(exprs, depth),
+        ExprType::Print(args) => format!("print({})", list(args, depth)),
+        ExprType::Read => "read()".to_string(),
+        ExprType::If {
+            cond,
+            body,
+            elsebody,
+        } => format!(
+            "if {}{}",
+            head(cond, depth),
+            body_and_else(body, elsebody.as_deref(), depth)
+        ),
+        ExprType::While {
+            cond,
+            body,
+            step: Some(step),
+            elsebody,
+            label,
+        } => format!(
+            "{}for nil; {}; {}{}",
+            label_prefix(label.as_deref()),
+            expr(cond, depth),
+            expr(step, depth),
+            body_and_else(body, elsebody.as_deref(), depth)
+        ),
+        ExprType::While {
+            cond,
+            body,
+            step: None,
+            elsebody,
+            label,
+        } => format!(
+            "{}while {}{}",
+            label_prefix(label.as_deref()),
+            head(cond, depth),
+            body_and_else(body, elsebody.as_deref(), depth)
+        ),
+        ExprType::DoWhile { body, cond, label } => format!(
+            "{}do {} while {}",
+            label_prefix(label.as_deref()),
+            expr(body, depth),
+            expr(cond, depth)
+        ),
+        ExprType::ForIn {
+            var,
+            iter,
+            body,
+            elsebody,
+            label,
+        } => format!(
+            "{}for {} in {}{}",
+            label_prefix(label.as_deref()),
+            head(var, depth),
+            head(iter, depth),
+            body_and_else(body, elsebody.as_deref(), depth)
+        ),
+        ExprType::Times { count, body, label } => format!(
+            "{}times {}{}",
+            label_prefix(label.as_deref()),
+            head(count, depth),
+            body_and_else(body, None, depth)
+        ),
+        ExprType::Break(None) => "break".to_string(),
+        ExprType::Break(Some(label)) => format!("break {label}"),
+        ExprType::Continue(None) => "continue".to_string(),
+        ExprType::Continue(Some(label)) => format!("continue {label}"),
+        ExprType::Try { body, var, handler } => format!(
+            "try {} catch {var} {}",
+            expr(body, depth),
+            expr(handler, depth)
+        ),
+        ExprType::FnDef { args, body } => format!("fn({}) {}", args.join(", "), expr(body, depth)),
+        ExprType::FnCall { func, args } => {
+            format!("{}({})", postfix_target(func, depth), list(args, depth))
+        }
+        ExprType::VecDef(items) => format!("[{}]", list(items, depth)),
+        ExprType::VecGet { vec, idx } => match idx.as_slice() {
+            [Expr {
+                kind: ExprType::Str(field),
+                ..
+            }] if is_identifier(field) => format!("{}.{field}", postfix_target(vec, depth)),
+            _ => format!("{}[{}]", postfix_target(vec, depth), list(idx, depth)),
+        },
+        ExprType::ObjectDef(fields) if fields.is_empty() => "{=}".to_string(),
+        ExprType::ObjectDef(fields) => {
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|(k, v)| format!("{}: {}", expr(k, depth), expr(v, depth)))
+                .collect();
+            format!("{{= {} }}", fields.join(", "))
+        }
+        ExprType::Use { filename, names } => match names {
+            Some(names) => format!("use \"{filename}\" {{ {} }}", names.join(", ")),
+            None => format!("use \"{filename}\""),
+        },
+        ExprType::Return(val) => format!("return {}", expr(val, depth)),
+    }
+}
+
+fn block(exprs: &[Expr], depth: usize) -> String {
+    if exprs.is_empty() {
+        return "{}".to_string();
+    }
+    let indent = INDENT.repeat(depth + 1);
+    let lines: String = exprs
+        .iter()
+        .map(|e| format!("{indent}{}\n", expr(e, depth + 1)))
+        .collect();
+    format!("{{\n{lines}{}}}", INDENT.repeat(depth))
+}
+
+fn list(exprs: &[Expr], depth: usize) -> String {
+    exprs
+        .iter()
+        .map(|e| expr(e, depth))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn label_prefix(label: Option<&str>) -> String {
+    label.map(|l| format!("{l}: ")).unwrap_or_default()
+}
+
+/// A C style `for` loop is parsed into `{ init while cond body }` where the
+/// while loop has a step.
+#[allow(clippy::type_complexity)]
+fn for_loop(e: &Expr) -> Option<(&Expr, &Expr, &Expr, &Expr, Option<&Expr>, Option<&str>)> {
+    let ExprType::Block(exprs) = &e.kind else {
+        return None;
+    };
+    let [init, Expr {
+        kind:
+            ExprType::While {
+                cond,
+                body,
+                step: Some(step),
+                elsebody,
+                label,
+            },
+        ..
+    }] = exprs.as_slice()
+    else {
+        return None;
+    };
+    Some((
+        init,
+        cond,
+        step,
+        body,
+        elsebody.as_deref(),
+        label.as_deref(),
+    ))
+}
+
+/// The part of `if`, `while`, `for` and `times` in front of the body, which
+/// gets parentheses if it would otherwise run into the body.
+fn head(e: &Expr, depth: usize) -> String {
+    operand(e, depth)
+}
+
+/// The body of a statement that follows its head. A body that could be read
+/// as a continuation of the head, like `-x` in `if a -x`, goes on a new line.
+fn body_and_else(body: &Expr, elsebody: Option<&Expr>, depth: usize) -> String {
+    // The else would be taken by the body otherwise.
+    let wrap = elsebody.is_some() && open_else(body);
+    let body_at = |depth| match wrap {
+        true => format!("({})", expr(body, depth)),
+        false => expr(body, depth),
+    };
+    let mut text = body_at(depth);
+    if text.starts_with(['(', '[', '-', '+', '.']) {
+        text = format!("\n{}{}", INDENT.repeat(depth + 1), body_at(depth + 1));
+    } else {
+        text = format!(" {text}");
+    }
+    match elsebody {
+        Some(elsebody) => format!("{text} else {}", expr(elsebody, depth)),
+        None => text,
+    }
+}
+
+/// Whether an `else` written after the expression would belong to it.
+fn open_else(e: &Expr) -> bool {
+    if let Some((_, _, _, _, elsebody, _)) = for_loop(e) {
+        return elsebody.is_none_or(open_else);
+    }
+    match &e.kind {
+        ExprType::If { elsebody, .. }
+        | ExprType::While { elsebody, .. }
+        | ExprType::ForIn { elsebody, .. } => elsebody.as_deref().is_none_or(open_else),
+        ExprType::DoWhile { cond: e, .. }
+        | ExprType::Times { body: e, .. }
+        | ExprType::Try { handler: e, .. }
+        | ExprType::FnDef { body: e, .. }
+        | ExprType::Define { val: e, .. }
+        | ExprType::Assign { right: e, .. }
+        | ExprType::AssignOp { right: e, .. }
+        | ExprType::Return(e) => open_else(e),
+        _ => false,
+    }
+}
+
+/// Statements that keep parsing as far as they can, so they need parentheses
+/// when they are part of a larger expression.
+fn is_statement(e: &Expr) -> bool {
+    matches!(
+        e.kind,
+        ExprType::Define { .. }
+            | ExprType::Assign { .. }
+            | ExprType::AssignOp { .. }
+            | ExprType::Block(_)
+            | ExprType::Program(_)
+            | ExprType::If { .. }
+            | ExprType::While { .. }
+            | ExprType::DoWhile { .. }
+            | ExprType::ForIn { .. }
+            | ExprType::Times { .. }
+            | ExprType::Break(_)
+            | ExprType::Continue(_)
+            | ExprType::Try { .. }
+            | ExprType::FnDef { .. }
+            | ExprType::Use { .. }
+            | ExprType::Return(_)
+    )
+}
+
+fn operand(e: &Expr, depth: usize) -> String {
+    if is_statement(e) {
+        format!("({})", expr(e, depth))
+    } else {
+        expr(e, depth)
+    }
+}
+
+/// An operand of a binary operator that binds at least as tightly as `level`.
+fn binary_operand(e: &Expr, depth: usize, min_level: usize) -> String {
+    match &e.kind {
+        ExprType::BinaryOp { op, .. } if level(op) < min_level => format!("({})", expr(e, depth)),
+        _ => operand(e, depth),
+    }
+}
+
+/// The expression in front of a call, index or dot.
+fn postfix_target(e: &Expr, depth: usize) -> String {
+    match e.kind {
+        ExprType::Identifier(_)
+        | ExprType::Str(_)
+        | ExprType::Nil
+        | ExprType::Bool(_)
+        | ExprType::Print(_)
+        | ExprType::Read
+        | ExprType::FnCall { .. }
+        | ExprType::VecDef(_)
+        | ExprType::VecGet { .. }
+        | ExprType::ObjectDef(_) => expr(e, depth),
+        _ => format!("({})", expr(e, depth)),
+    }
+}
+
+fn level(op: &Operator) -> usize {
+    Operator::all_bin()
+        .iter()
+        .position(|ops| ops.contains(op))
+        .unwrap_or(usize::MAX)
+}
+
+fn symbol(op: &Operator) -> &'static str {
+    match op {
+        Operator::Add => "+",
+        Operator::Sub => "-",
+        Operator::Mul => "*",
+        Operator::Div => "/",
+        Operator::FloorDiv => "//",
+        Operator::Mod => "%",
+        Operator::XOr => "^",
+        Operator::And => "&&",
+        Operator::Or => "||",
+        Operator::Eq => "==",
+        Operator::Neq => "!=",
+        Operator::Less => "<",
+        Operator::LessEq => "<=",
+        Operator::Greater => ">",
+        Operator::GreaterEq => ">=",
+        Operator::Not => "!",
+        Operator::LeftShift => "<<",
+        Operator::RightShift => ">>",
+        Operator::BitAnd => "&",
+        Operator::BitOr => "|",
+    }
+}
+
+/// Whether `obj.name` can be written instead of `obj["name"]`.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && matches!(
+            TokenType::keyword_or_identifier(name),
+            TokenType::Identifier(_)
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::aoc::parse;
+
+    /// Formats the code and checks that it parses into the same tree and that
+    /// formatting it again doesn't change it.
+    fn round_trip(code: &str) -> String {
+        let expr = parse(code).unwrap();
+        let formatted = expr.format();
+        let again = parse(&formatted).unwrap_or_else(|e| panic!("{e}\n{formatted}"));
+        assert_eq!(format!("{again:?}"), format!("{expr:?}"), "\n{formatted}");
+        assert_eq!(again.format(), formatted);
+        formatted
+    }
+
+    #[test]
+    fn examples() {
+        for file in std::fs::read_dir("./examples").unwrap() {
+            let path = file.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "aoc") {
+                round_trip(&std::fs::read_to_string(path).unwrap());
+            }
+        }
+        round_trip(include_str!("std.aoc"));
+    }
+
+    #[test]
+    fn canonical() {
+        let code = "x=1+2*3
+if x>3 {print(x)} else print(  -x )
+f = fn(a,b) {
+  # comment
+  a+b }
+obj = {= \"a\":[1,2], \"if\": 2,}
+obj.a[0] += obj[\"if\"]";
+        assert_eq!(
+            round_trip(code),
+            "x = 1 + 2 * 3
+if x > 3 {
+    print(x)
+} else print(-x)
+f = fn(a, b) {
+    a + b
+}
+obj = {= \"a\": [1, 2], \"if\": 2 }
+obj.a[0] += obj[\"if\"]
+"
+        );
+        assert_eq!(round_trip(""), "");
+    }
+
+    #[test]
+    fn precedence() {
+        let code = "print((1 + 2) * 3, 1 - (2 - 3), (1 - 2) - 3, -(-1), !(a && b), (a = 1) + 2)
+v = (fn(x) x)(1) + (if a 1 else 2) * [1, 2][0]
+x = y = (1 << 2) < 3 == (4 >= 5) || 1.0 // 2.5 % 3
+print(7 / 2, +[1], (1).x)";
+        round_trip(code);
+    }
+
+    #[test]
+    fn statements() {
+        let code = "use \"std\" { map, sum }
+let total = 0
+outer: for i = 0; i < 3; i += 1 {
+    for [a, b] in [[1, 2]] if a < b break outer else total += 1
+    if i == 1 continue outer
+    continue
+}
+do total -= 1 while total > 0
+times 3 total *= 2
+while total < 100 total += 1 else print(\"no break\")
+r = try error(\"x\") catch e e.message
+g = fn() return 1, 2
+if a
+    -b
+if c
+    [d] else if e { } else f
+if g if h 1 else 2
+if i
+    (if j 1) else 2
+print(read(), nil, true, false, \"s\", {=}, {= x: 1 })";
+        round_trip(code);
+    }
+}
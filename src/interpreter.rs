@@ -1,20 +1,92 @@
-use std::{cell::RefCell, fmt::Display, io::Write, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Display,
+    io::Write,
+    mem::{discriminant, Discriminant},
+    rc::Rc,
+};
 
 use crate::{
+    bigint::BigInt,
     bytecode::Operation,
-    error::{RuntimeError, Stackable},
-    runtime::{Capture, Chunk, Value},
+    error::{ErrorType, RuntimeError, Stackable},
+    native::range_bounds,
+    runtime::{obj_get, Capture, Chunk, Iter, Value},
+    token::Snippet,
 };
 
 type Error = crate::error::Error<RuntimeError>;
 type Result<T> = crate::error::Result<T, RuntimeError>;
 
+/// Counts how many times each kind of operation was executed.
+#[derive(Debug, Default)]
+pub struct Profile {
+    counts: HashMap<Discriminant<Operation>, (String, usize)>,
+}
+
+impl Profile {
+    fn record(&mut self, op: Operation) {
+        self.counts
+            .entry(discriminant(&op))
+            .or_insert_with(|| (op.mnemonic(), 0))
+            .1 += 1;
+    }
+
+    /// Number of executed operations.
+    pub fn total(&self) -> usize {
+        self.counts.values().map(|(_, count)| count).sum()
+    }
+
+    /// Execution counts per operation, the most frequent first.
+    pub fn histogram(&self) -> Vec<(&str, usize)> {
+        let mut histogram: Vec<_> = self
+            .counts
+            .values()
+            .map(|(name, count)| (name.as_str(), *count))
+            .collect();
+        histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        histogram
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total().max(1);
+        for (name, count) in self.histogram() {
+            let percent = 100.0 * count as f64 / total as f64;
+            let bar = "#".repeat((percent / 2.0).round() as usize);
+            writeln!(f, "{name:<12}{count:>12} {percent:>5.1}% {bar}")?;
+        }
+        writeln!(f, "{:<12}{:>12}", "Total", self.total())
+    }
+}
+
+/// Operands of an arithmetic operation converted to a common number type.
+enum Promoted {
+    Rational((i128, i128), (i128, i128)),
+    Big(BigInt, BigInt),
+    Float(f64, f64),
+    Complex((f64, f64), (f64, f64)),
+}
+
 pub struct Interpreter<W: Write> {
     chunk: Rc<Chunk>,
     stack: Vec<Value>,
     idx: usize,
     pub output: Option<W>,
     debug: bool,
+    /// Stacks of finished calls, reused so that calls don't have to allocate.
+    spare_stacks: Vec<Vec<Value>>,
+    profile: Option<Profile>,
+    /// Active `try` blocks, innermost last.
+    handlers: Vec<Handler>,
+}
+
+/// Where execution continues when a runtime error happens inside `try`.
+struct Handler {
+    catch_idx: usize,
+    stack_len: usize,
 }
 
 impl<W: Write> Interpreter<W> {
@@ -25,6 +97,9 @@ impl<W: Write> Interpreter<W> {
             idx: 0,
             output: Some(output),
             debug: false,
+            spare_stacks: Vec::new(),
+            profile: None,
+            handlers: Vec::new(),
         }
     }
 
@@ -32,6 +107,15 @@ impl<W: Write> Interpreter<W> {
         self.debug = debug;
     }
 
+    /// Starts counting executed operations, see [`Interpreter::take_profile`].
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
     pub fn run(&mut self) -> Result<Value> {
         for i in self.stack.len()..self.chunk.num_var() {
             match &self.chunk.captured_vars[i] {
@@ -44,6 +128,9 @@ impl<W: Write> Interpreter<W> {
         }
         while let Some(&cmd) = self.chunk.bytecode.get(self.idx) {
             self.dump_stack();
+            if let Some(profile) = &mut self.profile {
+                profile.record(cmd);
+            }
             self.idx += 1;
             let result = match cmd {
                 Operation::Return => break,
@@ -53,10 +140,8 @@ impl<W: Write> Interpreter<W> {
                         captured, chunk, ..
                     } = &mut val
                     {
-                        for is_captured in chunk.captured_vars.iter() {
-                            if let Capture::Captured(idx) = is_captured {
-                                captured.push(self.stack[*idx].clone());
-                            }
+                        for &(_, idx) in chunk.init.captured.iter() {
+                            captured.push(self.stack[idx].clone());
                         }
                     }
                     self.stack.push(val);
@@ -76,8 +161,12 @@ impl<W: Write> Interpreter<W> {
                 Operation::Mul => self.binary(&Self::op_mul),
                 Operation::Div => self.binary(&Self::op_div),
                 Operation::Mod => self.binary(&Self::op_mod),
+                Operation::FloorDiv => self.binary(&Self::op_floor_div),
                 Operation::And => self.binary(&Self::op_and),
                 Operation::Or => self.binary(&Self::op_or),
+                Operation::XOr => self.binary(&Self::op_xor),
+                Operation::BitAnd => self.binary(&Self::op_bit_and),
+                Operation::BitOr => self.binary(&Self::op_bit_or),
                 Operation::Eq => self.binary(&Self::op_eq),
                 Operation::Neq => self.binary(&Self::op_neq),
                 Operation::Gt => self.binary(&Self::op_gt),
@@ -103,22 +192,56 @@ impl<W: Write> Interpreter<W> {
                 Operation::JumpBack(n) => self.jump(-(n as i64)),
                 Operation::Noop => Ok(()),
                 Operation::FnCall(n) => self.fn_call(n as usize),
-                Operation::Clone(idx) => Ok(self
-                    .stack
-                    .push(self.stack[self.stack.len() - 1 - idx as usize].clone())),
+                Operation::IterStart => self.iter_start(),
+                Operation::IterNext(n) => self.iter_next(n as i64),
+                Operation::IterRange(n) => self.iter_range(n as usize),
+                Operation::Clone(idx) => {
+                    self.stack
+                        .push(self.stack[self.stack.len() - 1 - idx as usize].clone());
+                    Ok(())
+                }
                 Operation::Swap(idx) => {
                     let len = self.stack.len();
                     self.stack.swap(len - 1, len - 1 - idx as usize);
                     Ok(())
                 }
+                Operation::TryBegin(n) => {
+                    self.handlers.push(Handler {
+                        catch_idx: self.idx + n as usize,
+                        stack_len: self.stack.len(),
+                    });
+                    Ok(())
+                }
+                Operation::TryEnd => {
+                    self.handlers.pop();
+                    Ok(())
+                }
             };
-            result.stack(self.chunk.pos[self.idx - 1], &self.chunk.code)?;
+            let Err(err) = result else {
+                continue;
+            };
+            let err = err.stack_in(
+                self.chunk.source_name.as_ref(),
+                self.chunk.pos[self.idx - 1],
+                &self.chunk.code,
+            );
+            let Some(handler) = self.handlers.pop() else {
+                return Err(err);
+            };
+            self.stack.truncate(handler.stack_len);
+            self.stack.push(caught_error(&err));
+            self.idx = handler.catch_idx;
         }
         Ok(self.stack.pop().expect("frame did not return a value"))
     }
 
     fn unary(&mut self, cmd: &dyn Fn(Value) -> Result<Value>) -> Result<()> {
         let v = self.stack.pop().expect("ran out of stack during execution");
+        if let Some((method, _)) = self.overload(&[&v]) {
+            let result = self.call(method, vec![v])?;
+            self.stack.push(result);
+            return Ok(());
+        }
         self.stack.push(cmd(v)?);
         Ok(())
     }
@@ -126,10 +249,54 @@ impl<W: Write> Interpreter<W> {
     fn binary(&mut self, cmd: &dyn Fn(Value, Value) -> Result<Value>) -> Result<()> {
         let right = self.stack.pop().expect("Ran out of stack during execution");
         let left = self.stack.pop().expect("Ran out of stack during execution");
+        if let Some((method, negate)) = self.overload(&[&left, &right]) {
+            let result = self.call(method, vec![left, right])?;
+            self.stack.push(if negate {
+                Self::op_not(result)?
+            } else {
+                result
+            });
+            return Ok(());
+        }
         self.stack.push(cmd(left, right)?);
         Ok(())
     }
 
+    /// Objects with a `"__type"` field can overload operators by storing a
+    /// function under the operator's name, e.g. `"__add"` for `+`. Returns
+    /// the function of the first operand that overloads the operation being
+    /// executed, and whether its result has to be negated.
+    fn overload(&self, operands: &[&Value]) -> Option<(Value, bool)> {
+        if !operands.iter().any(|v| matches!(v, Value::Obj(_))) {
+            return None;
+        }
+        let (name, negate) = match self.chunk.bytecode[self.idx - 1] {
+            Operation::Add => ("__add", false),
+            Operation::Sub => ("__sub", false),
+            Operation::Mul => ("__mul", false),
+            Operation::Div => ("__div", false),
+            Operation::Mod => ("__mod", false),
+            Operation::FloorDiv => ("__floordiv", false),
+            Operation::XOr => ("__xor", false),
+            Operation::Negate => ("__neg", false),
+            Operation::Eq => ("__eq", false),
+            Operation::Neq => ("__eq", true),
+            Operation::Lt => ("__lt", false),
+            Operation::Leq => ("__le", false),
+            Operation::Gt => ("__gt", false),
+            Operation::Geq => ("__ge", false),
+            _ => return None,
+        };
+        let type_key = Value::Str(Rc::new("__type".to_string()));
+        let name = Value::Str(Rc::new(name.to_string()));
+        operands.iter().find_map(|v| match v {
+            Value::Obj(o) if o.borrow().contains_key(&type_key) => {
+                o.borrow().get(&name).map(|f| (f.clone(), negate))
+            }
+            _ => None,
+        })
+    }
+
     fn tertiary(&mut self, cmd: &dyn Fn(Value, Value, Value) -> Result<Value>) -> Result<()> {
         let right = self.stack.pop().expect("Ran out of stack during execution");
         let mid = self.stack.pop().expect("Ran out of stack during execution");
@@ -148,11 +315,18 @@ impl<W: Write> Interpreter<W> {
     }
 
     fn set_var(&mut self, idx: usize) -> Result<()> {
-        let val = self
-            .stack
-            .last()
-            .expect("Ran out of stack during execution.")
-            .clone();
+        // Assignments used as statements are followed by a Pop, in which case
+        // the value can be moved into the variable instead of cloned.
+        let val = if self.chunk.bytecode.get(self.idx) == Some(&Operation::Pop) && !self.debug {
+            self.idx += 1;
+            if let Some(profile) = &mut self.profile {
+                profile.record(Operation::Pop);
+            }
+            self.stack.pop()
+        } else {
+            self.stack.last().cloned()
+        }
+        .expect("Ran out of stack during execution.");
 
         match &mut self.stack[idx] {
             Value::Ref(var) => *var.borrow_mut() = val,
@@ -163,91 +337,210 @@ impl<W: Write> Interpreter<W> {
 
     fn op_add(left: Value, right: Value) -> Result<Value> {
         let v = match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
+            (Value::Int(a), Value::Int(b)) => match a.checked_add(b) {
+                Some(v) => Value::Int(v),
+                None => Value::big(BigInt::from(a as i128 + b as i128)),
+            },
             (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
             (Value::Float(a), Value::Int(b)) | (Value::Int(b), Value::Float(a)) => {
                 Value::Float(a + b as f64)
             }
             (Value::Str(a), Value::Str(b)) => Value::Str(Rc::new((*a).clone() + &b)),
+            (Value::Bytes(a), Value::Bytes(b)) => Value::Bytes(Rc::new([&a[..], &b[..]].concat())),
             (Value::Vec(a), Value::Vec(b)) => {
                 let mut result = Vec::new();
                 result.extend(a.borrow().iter().cloned());
                 result.extend(b.borrow().iter().cloned());
                 Value::Vec(Rc::new(RefCell::new(result)))
             }
-            (a, b) => return Err(format!("Unsupported Add for {a} and {b}").into()),
+            (a, b) => match Self::promote(&a, &b) {
+                Some(Promoted::Rational((a, b), (c, d))) => Value::rational(a * d + c * b, b * d)?,
+                Some(Promoted::Big(a, b)) => Value::big(&a + &b),
+                Some(Promoted::Float(a, b)) => Value::Float(a + b),
+                Some(Promoted::Complex((a, b), (c, d))) => Value::Complex(a + c, b + d),
+                None => {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("Unsupported Add for {a} and {b}"),
+                    ))
+                }
+            },
         };
         Ok(v)
     }
 
     fn op_sub(left: Value, right: Value) -> Result<Value> {
         let v = match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a - b),
+            (Value::Int(a), Value::Int(b)) => match a.checked_sub(b) {
+                Some(v) => Value::Int(v),
+                None => Value::big(BigInt::from(a as i128 - b as i128)),
+            },
             (Value::Float(a), Value::Float(b)) => Value::Float(a - b),
             (Value::Float(a), Value::Int(b)) => Value::Float(a - b as f64),
             (Value::Int(a), Value::Float(b)) => Value::Float(a as f64 - b),
-            (a, b) => return Err(format!("Unsupported Sub for {a} and {b}").into()),
+            (a, b) => match Self::promote(&a, &b) {
+                Some(Promoted::Rational((a, b), (c, d))) => Value::rational(a * d - c * b, b * d)?,
+                Some(Promoted::Big(a, b)) => Value::big(&a - &b),
+                Some(Promoted::Float(a, b)) => Value::Float(a - b),
+                Some(Promoted::Complex((a, b), (c, d))) => Value::Complex(a - c, b - d),
+                None => {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("Unsupported Sub for {a} and {b}"),
+                    ))
+                }
+            },
         };
         Ok(v)
     }
 
     fn op_mul(left: Value, right: Value) -> Result<Value> {
         let v = match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a * b),
+            (Value::Int(a), Value::Int(b)) => match a.checked_mul(b) {
+                Some(v) => Value::Int(v),
+                None => Value::big(BigInt::from(a as i128 * b as i128)),
+            },
             (Value::Float(a), Value::Float(b)) => Value::Float(a * b),
             (Value::Float(a), Value::Int(b)) | (Value::Int(b), Value::Float(a)) => {
                 Value::Float(a * b as f64)
             }
             (Value::Str(a), Value::Int(b)) | (Value::Int(b), Value::Str(a)) => {
-                Value::Str(Rc::new(a.repeat(b as usize)))
+                Value::Str(Rc::new(a.repeat(repeat_count(a.len(), b)?)))
             }
             (Value::Vec(v), Value::Int(n)) | (Value::Int(n), Value::Vec(v)) => {
                 let v = v.borrow();
-                let mut result = Vec::with_capacity(v.len() * n as usize);
+                let n = repeat_count(v.len(), n)?;
+                let mut result = Vec::with_capacity(v.len() * n);
                 for _ in 0..n {
                     result.extend(v.iter().cloned());
                 }
                 Value::Vec(Rc::new(RefCell::new(result)))
             }
-            (a, b) => return Err(format!("Unsupported Mul for {a} and {b}").into()),
+            (a, b) => match Self::promote(&a, &b) {
+                Some(Promoted::Rational((a, b), (c, d))) => Value::rational(a * c, b * d)?,
+                Some(Promoted::Big(a, b)) => Value::big(&a * &b),
+                Some(Promoted::Float(a, b)) => Value::Float(a * b),
+                Some(Promoted::Complex((a, b), (c, d))) => {
+                    Value::Complex(a * c - b * d, a * d + b * c)
+                }
+                None => {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("Unsupported Mul for {a} and {b}"),
+                    ))
+                }
+            },
         };
         Ok(v)
     }
 
+    /// True division, which gives a float even for two integers. Only
+    /// rational and complex numbers stay what they are.
     fn op_div(left: Value, right: Value) -> Result<Value> {
         let v = match (left, right) {
-            (Value::Int(a), Value::Int(b)) if b != 0 => Value::Int(a / b),
+            (Value::Int(a), Value::Int(b)) if b != 0 => Value::Float(a as f64 / b as f64),
             (Value::Float(a), Value::Float(b)) if b != 0.0 => Value::Float(a / b),
             (Value::Float(a), Value::Int(b)) if b != 0 => Value::Float(a / b as f64),
             (Value::Int(a), Value::Float(b)) if b != 0.0 => Value::Float(a as f64 / b),
-            (a, b) => return Err(format!("Unsupported Div for {a} and {b}").into()),
+            (a, b) => match Self::promote(&a, &b) {
+                Some(Promoted::Rational((a, b), (c, d))) if c != 0 => {
+                    Value::rational(a * d, b * c)?
+                }
+                Some(Promoted::Big(a, b)) if !b.is_zero() => Value::Float(a.to_f64() / b.to_f64()),
+                Some(Promoted::Float(a, b)) if b != 0.0 => Value::Float(a / b),
+                Some(Promoted::Complex((a, b), (c, d))) if c != 0.0 || d != 0.0 => {
+                    let den = c * c + d * d;
+                    Value::Complex((a * c + b * d) / den, (b * c - a * d) / den)
+                }
+                _ => return Err(div_error("Div", &a, &b)),
+            },
+        };
+        Ok(v)
+    }
+
+    /// Division rounded down. Integers and rational numbers give an integer,
+    /// floats give a float without a fractional part.
+    fn op_floor_div(left: Value, right: Value) -> Result<Value> {
+        let v = match (left, right) {
+            (Value::Int(a), Value::Int(b)) if b != 0 => {
+                Value::big(BigInt::from(floor_div(a as i128, b as i128)))
+            }
+            (Value::Float(a), Value::Float(b)) if b != 0.0 => Value::Float((a / b).floor()),
+            (Value::Float(a), Value::Int(b)) if b != 0 => Value::Float((a / b as f64).floor()),
+            (Value::Int(a), Value::Float(b)) if b != 0.0 => Value::Float((a as f64 / b).floor()),
+            (a, b) => match Self::promote(&a, &b) {
+                Some(Promoted::Rational((a, b), (c, d))) if c != 0 => {
+                    Value::big(BigInt::from(floor_div(a * d, b * c)))
+                }
+                Some(Promoted::Big(a, b)) if !b.is_zero() => Value::big(a.div_floor(&b).unwrap()),
+                Some(Promoted::Float(a, b)) if b != 0.0 => Value::Float((a / b).floor()),
+                _ => return Err(div_error("FloorDiv", &a, &b)),
+            },
         };
         Ok(v)
     }
 
+    /// Converts mixed operands with at least one complex, rational or big number to
+    /// a common type. Other numbers are handled by the operations directly.
+    fn promote(left: &Value, right: &Value) -> Option<Promoted> {
+        match (left, right) {
+            (Value::Complex(..), _) | (_, Value::Complex(..)) => {
+                Some(Promoted::Complex(left.as_complex()?, right.as_complex()?))
+            }
+            (Value::Rational(..) | Value::BigInt(_), Value::Float(_))
+            | (Value::Float(_), Value::Rational(..) | Value::BigInt(_)) => {
+                Some(Promoted::Float(left.as_float()?, right.as_float()?))
+            }
+            (Value::BigInt(_), _) | (_, Value::BigInt(_)) => {
+                Some(Promoted::Big(left.as_big()?, right.as_big()?))
+            }
+            (Value::Rational(..), _) | (_, Value::Rational(..)) => Some(Promoted::Rational(
+                left.as_fraction()?,
+                right.as_fraction()?,
+            )),
+            _ => None,
+        }
+    }
+
+    /// The remainder of floor division, so it has the sign of the divisor and
+    /// `a % b` is in `[0, b)` for a positive `b`.
     fn op_mod(left: Value, right: Value) -> Result<Value> {
         let v = match (left, right) {
-            (Value::Int(a), Value::Int(b)) if b != 0 => Value::Int(a % b),
-            (Value::Float(a), Value::Float(b)) if b != 0. => Value::Float(a % b),
-            (Value::Float(a), Value::Int(b)) if b != 0 => Value::Float(a % b as f64),
-            (Value::Int(a), Value::Float(b)) if b != 0. => Value::Float(a as f64 % b),
-            (a, b) => return Err(format!("Unsupported Mod for {a} and {b}").into()),
+            (Value::Int(a), Value::Int(b)) if b != 0 => {
+                let r = a.checked_rem(b).unwrap_or(0);
+                Value::Int(if r != 0 && (r < 0) != (b < 0) {
+                    r + b
+                } else {
+                    r
+                })
+            }
+            (Value::Float(a), Value::Float(b)) if b != 0. => Value::Float(floor_mod(a, b)),
+            (Value::Float(a), Value::Int(b)) if b != 0 => Value::Float(floor_mod(a, b as f64)),
+            (Value::Int(a), Value::Float(b)) if b != 0. => Value::Float(floor_mod(a as f64, b)),
+            (a, b) => match Self::promote(&a, &b) {
+                Some(Promoted::Big(a, b)) if !b.is_zero() => Value::big(a.rem_floor(&b).unwrap()),
+                Some(Promoted::Float(a, b)) if b != 0.0 => Value::Float(floor_mod(a, b)),
+                _ => return Err(div_error("Mod", &a, &b)),
+            },
         };
         Ok(v)
     }
 
     fn op_not(v: Value) -> Result<Value> {
-        match v.truthy() {
-            true => Ok(Value::Int(0)),
-            false => Ok(Value::Int(1)),
-        }
+        Ok(Value::Bool(!v.truthy()))
     }
 
     fn op_negate(v: Value) -> Result<Value> {
         match v {
-            Value::Int(i) => Ok(Value::Int(-i)),
+            Value::Int(i) => Ok(match i.checked_neg() {
+                Some(v) => Value::Int(v),
+                None => Value::big(-&BigInt::from(i)),
+            }),
             Value::Float(f) => Ok(Value::Float(-f)),
-            v => Err(format!("Cannot negate {v}").into()),
+            Value::Complex(re, im) => Ok(Value::Complex(-re, -im)),
+            Value::Rational(n, d) => Ok(Value::Rational(-n, d)),
+            Value::BigInt(n) => Ok(Value::big(-&*n)),
+            v => Err(Error::typed(ErrorType::Type, format!("Cannot negate {v}"))),
         }
     }
 
@@ -256,7 +549,11 @@ impl<W: Write> Interpreter<W> {
             Value::Int(_) | Value::Float(_) => Ok(v),
             Value::Vec(v) => Ok(Value::Int(v.borrow().len() as i64)),
             Value::Str(s) => Ok(Value::Int(s.len() as i64)),
-            v => Err(format!("Unary + invalid for {v}").into()),
+            Value::Bytes(b) => Ok(Value::Int(b.len() as i64)),
+            v => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unary + invalid for {v}"),
+            )),
         }
     }
 
@@ -276,37 +573,91 @@ impl<W: Write> Interpreter<W> {
         }
     }
 
-    fn op_eq(left: Value, right: Value) -> Result<Value> {
-        if left == right {
-            Ok(Value::Int(1))
-        } else {
-            Ok(Value::Int(0))
+    /// Bitwise for integers, otherwise whether exactly one of the operands is
+    /// truthy.
+    fn op_xor(left: Value, right: Value) -> Result<Value> {
+        match (left, right) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a ^ b)),
+            // Big integers are integers too, so they don't fall back to
+            // comparing truthiness.
+            (a @ Value::BigInt(_), b @ (Value::Int(_) | Value::BigInt(_)))
+            | (a @ Value::Int(_), b @ Value::BigInt(_)) => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unsupported XOr for {a} and {b}"),
+            )),
+            (a, b) => Ok(Value::Bool(a.truthy() != b.truthy())),
         }
     }
 
+    fn op_bit_and(left: Value, right: Value) -> Result<Value> {
+        match (left, right) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a & b)),
+            (a, b) => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unsupported BitAnd for {a} and {b}"),
+            )),
+        }
+    }
+
+    fn op_bit_or(left: Value, right: Value) -> Result<Value> {
+        match (left, right) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a | b)),
+            (a, b) => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unsupported BitOr for {a} and {b}"),
+            )),
+        }
+    }
+
+    fn op_eq(left: Value, right: Value) -> Result<Value> {
+        Ok(Value::Bool(left == right))
+    }
+
     fn op_neq(left: Value, right: Value) -> Result<Value> {
         Self::op_not(Self::op_eq(left, right)?)
     }
 
     fn op_left_shift(left: Value, right: Value) -> Result<Value> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a << b)),
+            (a @ (Value::Int(_) | Value::BigInt(_)), Value::Int(b)) => {
+                let b = shift_count(b)?;
+                if b > MAX_LEFT_SHIFT {
+                    return Err(Error::typed(
+                        ErrorType::Value,
+                        format!("Cannot shift left by {b} bits, the most is {MAX_LEFT_SHIFT}"),
+                    ));
+                }
+                if let Value::Int(a) = a {
+                    // Shifting back tells whether any bits were lost.
+                    if b < 64 && (a << b) >> b == a {
+                        return Ok(Value::Int(a << b));
+                    }
+                }
+                Ok(Value::big(a.as_big().unwrap().shl(b)))
+            }
             (Value::Vec(v), val) => {
                 v.borrow_mut().push(val.clone());
                 Ok(val)
             }
-            (a, b) => Err(format!("Unsupported LeftShift for {a} and {b}").into()),
+            (a, b) => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unsupported LeftShift for {a} and {b}"),
+            )),
         }
     }
 
     fn op_right_shift(left: Value, right: Value) -> Result<Value> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a >> b)),
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a >> shift_count(b)?.min(63))),
+            (Value::BigInt(a), Value::Int(b)) => Ok(Value::big(a.shr(shift_count(b)?))),
             (val, Value::Vec(v)) => {
                 v.borrow_mut().push(val);
                 Ok(Value::Vec(v))
             }
-            (a, b) => Err(format!("Unsupported RightShift for {a} and {b}").into()),
+            (a, b) => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unsupported RightShift for {a} and {b}"),
+            )),
         }
     }
 
@@ -317,6 +668,26 @@ impl<W: Write> Interpreter<W> {
         Ok(())
     }
 
+    /// Advances any iterator, calling the `next` function of objects.
+    pub(crate) fn next_value(&mut self, iter: &Rc<RefCell<Iter>>) -> Result<Option<Value>> {
+        // Objects are borrowed only to look up their next function, so that
+        // it can freely use the iterator itself.
+        let object = match &*iter.borrow() {
+            Iter::Object(Value::Obj(o)) => Some(o.clone()),
+            _ => None,
+        };
+        match object {
+            Some(o) => {
+                let next = o.borrow().get(&Value::Str(Rc::new("next".into()))).cloned();
+                let next =
+                    next.ok_or_else(|| Error::new("Iterator lost its next function".into()))?;
+                let val = self.call(next, Vec::new())?;
+                Ok((val != Value::Nil).then_some(val))
+            }
+            None => Ok(builtin_next(&mut iter.borrow_mut())),
+        }
+    }
+
     fn jump(&mut self, n: i64) -> Result<()> {
         if n > 0 {
             self.idx += n as usize;
@@ -328,99 +699,151 @@ impl<W: Write> Interpreter<W> {
 
     fn op_gt(left: Value, right: Value) -> Result<Value> {
         match left.partial_cmp(&right) {
-            Some(std::cmp::Ordering::Greater) => Ok(Value::Int(1)),
-            Some(std::cmp::Ordering::Equal) => Ok(Value::Int(0)),
-            Some(std::cmp::Ordering::Less) => Ok(Value::Int(0)),
-            None => Err(format!("Cannot compare {left:?} and {right:?}").into()),
+            Some(std::cmp::Ordering::Greater) => Ok(Value::Bool(true)),
+            Some(std::cmp::Ordering::Equal) => Ok(Value::Bool(false)),
+            Some(std::cmp::Ordering::Less) => Ok(Value::Bool(false)),
+            None => Err(Error::typed(
+                ErrorType::Type,
+                format!("Cannot compare {left:?} and {right:?}"),
+            )),
         }
     }
 
     fn op_geq(left: Value, right: Value) -> Result<Value> {
         match left.partial_cmp(&right) {
-            Some(std::cmp::Ordering::Greater) => Ok(Value::Int(1)),
-            Some(std::cmp::Ordering::Equal) => Ok(Value::Int(1)),
-            Some(std::cmp::Ordering::Less) => Ok(Value::Int(0)),
-            None => Err(format!("Cannot compare {left:?} and {right:?}").into()),
+            Some(std::cmp::Ordering::Greater) => Ok(Value::Bool(true)),
+            Some(std::cmp::Ordering::Equal) => Ok(Value::Bool(true)),
+            Some(std::cmp::Ordering::Less) => Ok(Value::Bool(false)),
+            None => Err(Error::typed(
+                ErrorType::Type,
+                format!("Cannot compare {left:?} and {right:?}"),
+            )),
         }
     }
 
     fn op_lt(left: Value, right: Value) -> Result<Value> {
         match left.partial_cmp(&right) {
-            Some(std::cmp::Ordering::Greater) => Ok(Value::Int(0)),
-            Some(std::cmp::Ordering::Equal) => Ok(Value::Int(0)),
-            Some(std::cmp::Ordering::Less) => Ok(Value::Int(1)),
-            None => Err(format!("Cannot compare {left:?} and {right:?}").into()),
+            Some(std::cmp::Ordering::Greater) => Ok(Value::Bool(false)),
+            Some(std::cmp::Ordering::Equal) => Ok(Value::Bool(false)),
+            Some(std::cmp::Ordering::Less) => Ok(Value::Bool(true)),
+            None => Err(Error::typed(
+                ErrorType::Type,
+                format!("Cannot compare {left:?} and {right:?}"),
+            )),
         }
     }
 
     fn op_leq(left: Value, right: Value) -> Result<Value> {
         match left.partial_cmp(&right) {
-            Some(std::cmp::Ordering::Greater) => Ok(Value::Int(0)),
-            Some(std::cmp::Ordering::Equal) => Ok(Value::Int(1)),
-            Some(std::cmp::Ordering::Less) => Ok(Value::Int(1)),
-            None => Err(format!("Cannot compare {left:?} and {right:?}").into()),
+            Some(std::cmp::Ordering::Greater) => Ok(Value::Bool(false)),
+            Some(std::cmp::Ordering::Equal) => Ok(Value::Bool(true)),
+            Some(std::cmp::Ordering::Less) => Ok(Value::Bool(true)),
+            None => Err(Error::typed(
+                ErrorType::Type,
+                format!("Cannot compare {left:?} and {right:?}"),
+            )),
         }
     }
     fn op_vec_get(index: Value, vec: Value) -> Result<Value> {
         match (vec, index) {
             (Value::Vec(v), Value::Int(i)) => {
                 let v = v.borrow();
-                let val = v.get(wrap_vec_idx(i, v.len())).ok_or::<Error>(
-                    format!("Index {i} out of range for vector of length {}", v.len()).into(),
-                )?;
+                let val = wrap_vec_idx(i, v.len())
+                    .and_then(|i| v.get(i))
+                    .ok_or_else(|| {
+                        Error::typed(
+                            ErrorType::Index,
+                            format!("Index {i} out of range for vector of length {}", v.len()),
+                        )
+                    })?;
                 Ok(val.clone())
             }
             (Value::Str(s), Value::Int(i)) => Ok(Value::Int(
-                *s.as_bytes().get(wrap_vec_idx(i, s.len())).ok_or::<Error>(
-                    format!(
-                        "String index {i} out of range for string of length {}",
-                        s.len()
-                    )
-                    .into(),
-                )? as i64,
+                *wrap_vec_idx(i, s.len())
+                    .and_then(|i| s.as_bytes().get(i))
+                    .ok_or_else(|| {
+                        Error::typed(
+                            ErrorType::Index,
+                            format!(
+                                "String index {i} out of range for string of length {}",
+                                s.len()
+                            ),
+                        )
+                    })? as i64,
+            )),
+            (Value::Bytes(b), Value::Int(i)) => Ok(Value::Int(
+                *wrap_vec_idx(i, b.len())
+                    .and_then(|i| b.get(i))
+                    .ok_or_else(|| {
+                        Error::typed(
+                            ErrorType::Index,
+                            format!(
+                                "Bytes index {i} out of range for bytes of length {}",
+                                b.len()
+                            ),
+                        )
+                    })? as i64,
+            )),
+            (Value::Obj(o), v) => Ok(obj_get(&mut o.borrow_mut(), &v)),
+            (a, b) => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unsupported VecGet for {}[{}]", a, b),
             )),
-            (Value::Obj(o), v) => Ok(o.borrow().get(&v).unwrap_or(&Value::Nil).clone()),
-            (a, b) => Err(format!("Unsupported VecGet for {}[{}]", a, b).into()),
         }
     }
     fn op_vec_slice(start_idx: Value, end_idx: Value, vec: Value) -> Result<Value> {
         match (vec, start_idx, end_idx) {
             (Value::Vec(v), Value::Int(s), Value::Int(e)) => {
                 let v = v.borrow();
-                let s = wrap_vec_idx(s, v.len());
-                let e = wrap_vec_idx(e, v.len());
-                Ok(Value::Vec(Rc::new(RefCell::new(
-                    v[s..e].into_iter().cloned().collect(),
-                ))))
+                let slice = slice_range(s, e, v.len()).and_then(|r| v.get(r));
+                let slice = slice.ok_or_else(|| slice_error("vector", s, e, v.len()))?;
+                Ok(Value::Vec(Rc::new(RefCell::new(slice.to_vec()))))
             }
             (Value::Str(st), Value::Int(s), Value::Int(e)) => {
-                let s = wrap_vec_idx(s, st.len());
-                let e = wrap_vec_idx(e, st.len());
-                Ok(Value::Str(Rc::new(st[s..e].to_string())))
+                let slice = slice_range(s, e, st.len()).and_then(|r| st.get(r));
+                let slice = slice.ok_or_else(|| slice_error("string", s, e, st.len()))?;
+                Ok(Value::Str(Rc::new(slice.to_string())))
             }
-            (a, b, c) => Err(format!("Unsupported VecGet for {a}[{b},{c}]").into()),
+            (Value::Bytes(b), Value::Int(s), Value::Int(e)) => {
+                let slice = slice_range(s, e, b.len()).and_then(|r| b.get(r));
+                let slice = slice.ok_or_else(|| slice_error("bytes", s, e, b.len()))?;
+                Ok(Value::Bytes(Rc::new(slice.to_vec())))
+            }
+            (a, b, c) => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unsupported VecGet for {a}[{b},{c}]"),
+            )),
         }
     }
     fn op_vec_set(value: Value, vec: Value, index: Value) -> Result<Value> {
         match (vec, index) {
             (Value::Vec(v), Value::Int(i)) => {
                 let mut val = v.borrow_mut();
-                let i = wrap_vec_idx(i, val.len());
-                val[i] = value.clone();
+                let len = val.len();
+                let slot = wrap_vec_idx(i, len).and_then(|i| val.get_mut(i));
+                let slot = slot.ok_or_else(|| {
+                    Error::typed(
+                        ErrorType::Index,
+                        format!("Index {i} out of range for vector of length {len}"),
+                    )
+                })?;
+                *slot = value.clone();
                 Ok(value)
             }
             (Value::Obj(o), index) => {
                 o.borrow_mut().insert(index, value.clone());
                 Ok(value)
             }
-            (a, b) => Err(format!("Unsupported VecSet for {a}[{b}]").into()),
+            (a, b) => Err(Error::typed(
+                ErrorType::Type,
+                format!("Unsupported VecSet for {a}[{b}]"),
+            )),
         }
     }
+    /// Collects the top `size` values into a vector, in the order they were
+    /// pushed.
     fn vec_collect(&mut self, size: usize) -> Result<()> {
-        let mut vec = Vec::with_capacity(size);
-        for _ in 0..size {
-            vec.push(self.stack.pop().expect("Ran out of stack"));
-        }
+        let vec = self.stack.split_off(self.stack.len() - size);
         self.stack.push(Value::Vec(Rc::new(RefCell::new(vec))));
         Ok(())
     }
@@ -429,11 +852,29 @@ impl<W: Write> Interpreter<W> {
         {
             let vec = self.stack.last().expect("Ran out of stack");
             let Value::Vec(vec) = vec else {
-                return Err(format!("Can only unpack vector not {vec:?}").into());
+                return Err(Error::typed(
+                    ErrorType::Type,
+                    format!("Can only unpack a vector into {size} variables, not {vec}"),
+                ));
             };
             let vec = vec.borrow();
-            if vec.len() != size {
-                return Err(format!("Expected vector of length {size}, got {}", vec.len()).into());
+            if vec.len() < size {
+                return Err(Error::typed(
+                    ErrorType::Value,
+                    format!(
+                        "Not enough values to unpack: expected {size}, got {}",
+                        vec.len()
+                    ),
+                ));
+            }
+            if vec.len() > size {
+                return Err(Error::typed(
+                    ErrorType::Value,
+                    format!(
+                        "Too many values to unpack: expected {size}, got {}",
+                        vec.len()
+                    ),
+                ));
             }
             for val in vec.iter().rev() {
                 unpacked_values.push(val.clone());
@@ -443,14 +884,19 @@ impl<W: Write> Interpreter<W> {
         Ok(())
     }
 
+    #[allow(clippy::mutable_key_type)]
     fn obj_collect(&mut self, size: usize) -> Result<()> {
         let mut obj = std::collections::HashMap::with_capacity(size);
-        for _ in 0..size {
-            let val = self.stack.pop().expect("Ran out of stack");
-            let key = self.stack.pop().expect("Ran out of stack");
+        let mut fields = self
+            .stack
+            .split_off(self.stack.len() - 2 * size)
+            .into_iter();
+        // In order, so that a repeated key keeps its last value.
+        while let (Some(key), Some(val)) = (fields.next(), fields.next()) {
             obj.insert(key, val);
         }
-        self.stack.push(Value::Obj(Rc::new(RefCell::new(obj))));
+        self.stack
+            .push(Value::Obj(Rc::new(RefCell::new(obj.into()))));
         Ok(())
     }
 
@@ -466,24 +912,64 @@ impl<W: Write> Interpreter<W> {
     }
 
     fn read(&mut self) -> Result<()> {
-        let mut input = String::new();
-        let val = match std::io::stdin().read_line(&mut input) {
-            Ok(_) if input.len() > 0 => {
-                if input.bytes().last() == Some(b'\n') {
-                    input.pop();
-                }
-                Value::Str(Rc::new(input))
-            }
-            _ => Value::Nil,
-        };
+        let val = read_line().map_or(Value::Nil, |line| Value::Str(Rc::new(line)));
         self.stack.push(val);
         Ok(())
     }
 
+    fn iter_start(&mut self) -> Result<()> {
+        let iter = to_iter(self.stack.pop().expect("Ran out of stack"))?;
+        self.stack.push(Value::Iter(iter));
+        Ok(())
+    }
+
+    fn iter_range(&mut self, num_args: usize) -> Result<()> {
+        let args = self.stack.split_off(self.stack.len() - num_args);
+        let (next, end, step) = range_bounds(&args)?;
+        self.stack.push(Iter::Range { next, end, step }.into());
+        Ok(())
+    }
+
+    /// The iterator sits right below the value of the previous iteration.
+    fn iter_next(&mut self, n: i64) -> Result<()> {
+        let Value::Iter(iter) = &self.stack[self.stack.len() - 2] else {
+            // Only loaded bytecode that was tampered with can get here.
+            return Err(Error::new("IterNext without an iterator".to_string()));
+        };
+        let iter = iter.clone();
+        match self.next_value(&iter)? {
+            Some(val) => self.stack.push(val),
+            None => self.jump(n)?,
+        }
+        Ok(())
+    }
+
     fn fn_call(&mut self, num_args: usize) -> Result<()> {
         let func = self.stack.pop().expect("Ran out of stack.");
+        let mut args = self.spare_stacks.pop().unwrap_or_default();
+        args.extend(self.stack.drain(self.stack.len() - num_args..));
+        let result = self.call(func, args)?;
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /// Calls a function or native value with already evaluated arguments.
+    pub(crate) fn call(&mut self, func: Value, args: Vec<Value>) -> Result<Value> {
+        if let Value::Native(native) = func {
+            return self.call_native(native, args);
+        }
+        let num_args = args.len();
         if self.debug {
-            writeln!(self.output.as_mut().unwrap(), "=== Function {func} ===",).unwrap();
+            let source = match &func {
+                Value::Fn { chunk, .. } => chunk.source_name.as_ref(),
+                _ => None,
+            };
+            let source = source.map(|s| format!(" in {s}")).unwrap_or_default();
+            writeln!(
+                self.output.as_mut().unwrap(),
+                "=== Function {func}{source} ===",
+            )
+            .unwrap();
         }
         let Value::Fn {
             num_params,
@@ -491,46 +977,54 @@ impl<W: Write> Interpreter<W> {
             chunk,
         } = func
         else {
-            return Err(format!("Only functions can be called, not {func:?}.").into());
+            return Err(Error::typed(
+                ErrorType::Type,
+                format!("Only functions can be called, not {func:?}."),
+            ));
         };
         if num_params != num_args {
-            return Err(format!("function expects {num_params} args, but got {num_args}").into());
+            return Err(Error::typed(
+                ErrorType::Call,
+                format!("function expects {num_params} args, but got {num_args}"),
+            ));
+        }
+        if captured.len() != chunk.init.captured.len() {
+            return Err(Error::typed(
+                ErrorType::Call,
+                format!(
+                    "function captures {} variables, but got {} values",
+                    chunk.init.captured.len(),
+                    captured.len()
+                ),
+            ));
         }
-        let args = self.stack.split_off(self.stack.len() - num_args);
         if self.debug {
             writeln!(self.output.as_mut().unwrap(), "{chunk}").unwrap();
         }
-        let mut executor = Self::new(chunk, self.output.take().unwrap());
-        executor.set_debug(self.debug);
-        for (arg, captured) in args.into_iter().zip(executor.chunk.captured_vars.iter()) {
-            match captured {
-                Capture::Local => executor.stack.push(arg),
-                Capture::Owned => executor.stack.push(Value::Ref(Rc::new(RefCell::new(arg)))),
-                Capture::Captured(_) => todo!(),
-            }
+        let mut stack = args;
+        stack.resize(chunk.num_var(), Value::Nil);
+        for &idx in chunk.init.owned.iter() {
+            let val = std::mem::replace(&mut stack[idx], Value::Nil);
+            stack[idx] = Value::Ref(Rc::new(RefCell::new(val)));
         }
-        let mut captured = captured.iter();
-        for is_captured in executor.chunk.captured_vars.iter().skip(num_args) {
-            match is_captured {
-                Capture::Local => executor.stack.push(Value::Nil),
-                Capture::Owned => executor
-                    .stack
-                    .push(Value::Ref(Rc::new(RefCell::new(Value::Nil)))),
-                Capture::Captured(_) => executor.stack.push(captured.next().unwrap().clone()),
-            }
+        for (&(idx, _), val) in chunk.init.captured.iter().zip(captured) {
+            stack[idx] = val;
         }
+        let mut executor = Self::new(chunk, self.output.take().unwrap());
+        executor.set_debug(self.debug);
+        executor.stack = stack;
+        executor.spare_stacks = std::mem::take(&mut self.spare_stacks);
+        executor.profile = self.profile.take();
         let result = executor.run();
+        self.profile = executor.profile.take();
         self.output = Some(executor.output.take().unwrap());
+        self.spare_stacks = std::mem::take(&mut executor.spare_stacks);
+        executor.stack.clear();
+        self.spare_stacks.push(executor.stack);
         if self.debug {
             writeln!(self.output.as_mut().unwrap(), "=== Exit function ===").unwrap();
         }
-        match result {
-            Ok(val) => {
-                self.stack.push(val);
-                Ok(())
-            }
-            Err(e) => Err(e),
-        }
+        result
     }
     fn dump_stack(&mut self) {
         if !self.debug {
@@ -543,9 +1037,14 @@ impl<W: Write> Interpreter<W> {
             .enumerate()
             .rev()
             .for_each(|(i, v)| writeln!(f, "{i}: {v}").unwrap());
+        let Snippet {
+            line, col, snippet, ..
+        } = self.chunk.pos[self.idx].extract(&self.chunk.code);
+        // Only the first line of operations that span several.
+        let snippet = snippet.lines().next().unwrap_or_default();
         writeln!(
             f,
-            "=== Next operation ===\n{}: {:?}",
+            "=== Next operation ===\n{}: {:?} on line {line}:{col}: {snippet}",
             self.idx, self.chunk.bytecode[self.idx]
         )
         .unwrap();
@@ -553,14 +1052,164 @@ impl<W: Write> Interpreter<W> {
     }
 }
 
-fn wrap_vec_idx(idx: i64, len: usize) -> usize {
+/// The value a `catch` handler gets, an object with the `kind` of the error
+/// and its `message`.
+fn caught_error(err: &Error) -> Value {
+    let key = |s: &str| Value::Str(Rc::new(s.to_string()));
+    #[allow(clippy::mutable_key_type)]
+    let obj = HashMap::from([
+        (key("kind"), key(err.error_type().name())),
+        (key("message"), Value::Str(Rc::new(err.message()))),
+    ]);
+    Value::Obj(Rc::new(RefCell::new(obj.into())))
+}
+
+/// Dividing two numbers only fails when the divisor is zero, other operands
+/// have the wrong type.
+fn div_error(op: &str, a: &Value, b: &Value) -> Error {
+    let ty = match (a.as_complex(), b.as_complex()) {
+        (Some(_), Some((0.0, 0.0))) => ErrorType::ZeroDivision,
+        _ => ErrorType::Type,
+    };
+    Error::typed(ty, format!("Unsupported {op} for {a} and {b}"))
+}
+
+/// Shifting left by more bits than this is an error, as the result wouldn't
+/// fit into memory for much larger counts.
+const MAX_LEFT_SHIFT: usize = 1 << 24;
+
+fn shift_count(n: i64) -> Result<usize> {
+    usize::try_from(n).map_err(|_| {
+        Error::typed(
+            ErrorType::Value,
+            format!("Cannot shift by a negative number of bits, {n}"),
+        )
+    })
+}
+
+/// Repeating a string or vector can't make it longer than this, so a typo in
+/// the count is an error instead of running out of memory.
+const MAX_REPEAT_LEN: usize = 1 << 24;
+
+/// How many times to repeat something of length `len`, where a negative count
+/// gives an empty result.
+fn repeat_count(len: usize, n: i64) -> Result<usize> {
+    if len == 0 || n <= 0 {
+        return Ok(0);
+    }
+    match len.checked_mul(n as usize) {
+        Some(total) if total <= MAX_REPEAT_LEN => Ok(n as usize),
+        _ => Err(Error::typed(
+            ErrorType::Value,
+            format!("Cannot repeat a length of {len} {n} times, the most is {MAX_REPEAT_LEN}"),
+        )),
+    }
+}
+
+/// Integer division that rounds towards negative infinity instead of zero.
+fn floor_div(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+fn floor_mod(a: f64, b: f64) -> f64 {
+    let r = a % b;
+    if r != 0.0 && (r < 0.0) != (b < 0.0) {
+        r + b
+    } else {
+        r
+    }
+}
+
+/// Negative indices count from the end. Returns `None` for negative indices
+/// before the start, larger ones are left for the caller to check.
+fn wrap_vec_idx(idx: i64, len: usize) -> Option<usize> {
     if idx < 0 {
-        len - (-idx) as usize
+        len.checked_sub(idx.unsigned_abs() as usize)
     } else {
-        idx as usize
+        Some(idx as usize)
     }
 }
-pub fn fmt_vec<T>(f: &mut std::fmt::Formatter<'_>, v: &Vec<T>) -> std::fmt::Result
+
+fn slice_range(start: i64, end: i64, len: usize) -> Option<std::ops::Range<usize>> {
+    let (start, end) = (wrap_vec_idx(start, len)?, wrap_vec_idx(end, len)?);
+    (start <= end && end <= len).then_some(start..end)
+}
+
+fn slice_error(what: &str, start: i64, end: i64, len: usize) -> Error {
+    Error::typed(
+        ErrorType::Index,
+        format!("Slice [{start}, {end}] out of range for {what} of length {len}"),
+    )
+}
+fn builtin_next(iter: &mut Iter) -> Option<Value> {
+    match iter {
+        Iter::Range { next, end, step } => {
+            let val = *next;
+            if (*step > 0 && val < *end) || (*step < 0 && val > *end) {
+                *next = val.checked_add(*step).unwrap_or(*end);
+                Some(Value::Int(val))
+            } else {
+                None
+            }
+        }
+        Iter::Vec { vec, idx } => {
+            *idx += 1;
+            vec.borrow().get(*idx - 1).cloned()
+        }
+        Iter::Str { s, idx } => {
+            *idx += 1;
+            s.as_bytes().get(*idx - 1).map(|&c| Value::Int(c as i64))
+        }
+        Iter::Bytes { b, idx } => {
+            *idx += 1;
+            b.get(*idx - 1).map(|&c| Value::Int(c as i64))
+        }
+        Iter::Lines => read_line().map(|line| Value::Str(Rc::new(line))),
+        Iter::Object(_) => unreachable!("objects are iterated by the interpreter"),
+    }
+}
+
+/// Turns anything a `for` loop can walk over into an iterator.
+pub(crate) fn to_iter(val: Value) -> Result<Rc<RefCell<Iter>>> {
+    let iter = match val {
+        Value::Iter(iter) => return Ok(iter),
+        Value::Vec(vec) => Iter::Vec { vec, idx: 0 },
+        Value::Str(s) => Iter::Str { s, idx: 0 },
+        Value::Bytes(b) => Iter::Bytes { b, idx: 0 },
+        Value::Obj(o) if o.borrow().contains_key(&Value::Str(Rc::new("next".into()))) => {
+            Iter::Object(Value::Obj(o))
+        }
+        v => {
+            return Err(Error::typed(
+                ErrorType::Type,
+                format!("Cannot iterate over {v}"),
+            ))
+        }
+    };
+    Ok(Rc::new(RefCell::new(iter)))
+}
+
+/// Reads a line from stdin without the trailing newline, or `None` at the end
+/// of the input.
+fn read_line() -> Option<String> {
+    let mut input = String::new();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(_) if !input.is_empty() => {
+            if input.bytes().last() == Some(b'\n') {
+                input.pop();
+            }
+            Some(input)
+        }
+        _ => None,
+    }
+}
+
+pub fn fmt_vec<T>(f: &mut std::fmt::Formatter<'_>, v: &[T]) -> std::fmt::Result
 where
     T: Display,
 {
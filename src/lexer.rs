@@ -1,6 +1,9 @@
 use std::{iter::Peekable, rc::Rc};
 
-use crate::token::{Token, TokenType};
+use crate::{
+    bigint::BigInt,
+    token::{Token, TokenType},
+};
 
 #[derive(Debug, Clone)]
 pub struct Lexer {
@@ -28,34 +31,71 @@ impl Lexer {
                 return Token::new(self.input.len(), self.input.len(), TokenType::EOF);
             };
             return match c {
-                '&' => self.one_or_two('&', TokenType::And, TokenType::AndAnd),
-                '|' => self.one_or_two('|', TokenType::Pipe, TokenType::PipePipe),
-                '!' => self.one_or_two('=', TokenType::Bang, TokenType::BangEq),
-                '=' => self.one_or_two('=', TokenType::Eq, TokenType::EqEq),
-                '<' => self.one_or_twos(
-                    TokenType::Less,
-                    &[('=', TokenType::LessEq), ('<', TokenType::LessLess)],
+                '&' => self.one_or_twos(
+                    TokenType::And,
+                    &[('&', TokenType::AndAnd), ('=', TokenType::AndEq)],
                 ),
-                '>' => self.one_or_twos(
-                    TokenType::Greater,
-                    &[
-                        ('=', TokenType::GreaterEq),
-                        ('>', TokenType::GreaterGreater),
-                    ],
+                '|' => self.one_or_twos(
+                    TokenType::Pipe,
+                    &[('|', TokenType::PipePipe), ('=', TokenType::PipeEq)],
                 ),
+                '^' => self.one_or_two('=', TokenType::Caret, TokenType::CaretEq),
+                '!' => self.one_or_two('=', TokenType::Bang, TokenType::BangEq),
+                '=' => self.one_or_two('=', TokenType::Eq, TokenType::EqEq),
+                '<' => {
+                    let token = self.one_or_twos(
+                        TokenType::Less,
+                        &[('=', TokenType::LessEq), ('<', TokenType::LessLess)],
+                    );
+                    match token.kind {
+                        TokenType::LessLess => self.extend(token, '=', TokenType::LessLessEq),
+                        _ => token,
+                    }
+                }
+                '>' => {
+                    let token = self.one_or_twos(
+                        TokenType::Greater,
+                        &[
+                            ('=', TokenType::GreaterEq),
+                            ('>', TokenType::GreaterGreater),
+                        ],
+                    );
+                    match token.kind {
+                        TokenType::GreaterGreater => {
+                            self.extend(token, '=', TokenType::GreaterGreaterEq)
+                        }
+                        _ => token,
+                    }
+                }
                 '{' => self.one_or_two('=', TokenType::LBrace, TokenType::OBrace),
                 '}' => self.one(TokenType::RBrace),
                 '(' => self.one(TokenType::LParen),
                 ')' => self.one(TokenType::RParen),
                 '[' => self.one(TokenType::LBracket),
                 ']' => self.one(TokenType::RBracket),
-                '+' => self.one_or_two('=', TokenType::Plus, TokenType::PlusEq),
-                '-' => self.one_or_two('=', TokenType::Minus, TokenType::MinusEq),
+                '+' => self.one_or_twos(
+                    TokenType::Plus,
+                    &[('=', TokenType::PlusEq), ('+', TokenType::PlusPlus)],
+                ),
+                '-' => self.one_or_twos(
+                    TokenType::Minus,
+                    &[('=', TokenType::MinusEq), ('-', TokenType::MinusMinus)],
+                ),
                 '*' => self.one_or_two('=', TokenType::Star, TokenType::StarEq),
-                '/' => self.one_or_two('=', TokenType::Slash, TokenType::SlashEq),
+                '/' => {
+                    let token = self.one_or_twos(
+                        TokenType::Slash,
+                        &[('=', TokenType::SlashEq), ('/', TokenType::SlashSlash)],
+                    );
+                    match token.kind {
+                        TokenType::SlashSlash => self.extend(token, '=', TokenType::SlashSlashEq),
+                        _ => token,
+                    }
+                }
                 '%' => self.one_or_two('=', TokenType::Percent, TokenType::PercentEq),
                 '\n' | ';' => self.one(TokenType::EOL),
                 ',' => self.one(TokenType::Comma),
+                ':' => self.one(TokenType::Colon),
                 '.' => self.one(TokenType::Dot),
                 'a'..='z' | 'A'..='Z' | '_' => self.keyword_or_identifier(),
                 '0'..='9' => self.number(),
@@ -91,13 +131,25 @@ impl Lexer {
         Token::new(start, start + first.len_utf8(), default)
     }
 
+    /// Turns the token into `kind` if it's followed by `if_char`.
+    fn extend(&mut self, token: Token, if_char: char, kind: TokenType) -> Token {
+        match self.iter.peek() {
+            Some(&(end, c)) if c == if_char => {
+                self.iter.next();
+                Token::new(token.pos.start, end + c.len_utf8(), kind)
+            }
+            _ => token,
+        }
+    }
+
     fn comment(&mut self) -> Token {
-        let (start, mut last) = self.iter.next().expect("Needs one character");
-        let mut end = 0;
-        let mut comment = last.to_string();
+        let (start, first) = self.iter.next().expect("Needs one character");
+        let mut end = start + first.len_utf8();
+        let mut comment = first.to_string();
         while matches!(self.iter.peek(), Some(&(_, c)) if c != '\n') {
-            (end, last) = self.iter.next().expect("peek() was Some");
-            comment.push(last);
+            let (idx, c) = self.iter.next().expect("peek() was Some");
+            end = idx + c.len_utf8();
+            comment.push(c);
         }
         Token::new(start, end, TokenType::Comment(comment))
     }
@@ -124,7 +176,7 @@ impl Lexer {
         let mut end = 0;
         let mut dot = false;
         while let Some((_, c)) = self.iter.peek() {
-            if !(('0'..='9').contains(c) || (!dot && c == &'.')) {
+            if !(c.is_ascii_digit() || (!dot && c == &'.')) {
                 break;
             }
             (end, last) = self.iter.next().expect("peek() was Some");
@@ -139,11 +191,11 @@ impl Lexer {
                 TokenType::Float(num.parse().expect("Only contains digits and one dot.")),
             )
         } else {
-            Token::new(
-                start,
-                end,
-                TokenType::Integer(num.parse().expect("Only contains digits.")),
-            )
+            let kind = match num.parse() {
+                Ok(n) => TokenType::Integer(n),
+                Err(_) => TokenType::BigInteger(BigInt::parse(num).expect("Only contains digits.")),
+            };
+            Token::new(start, end, kind)
         }
     }
 
@@ -187,6 +239,7 @@ impl Iterator for Lexer {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::token::Pos;
 
     #[test]
     fn arithmetic() {
@@ -208,6 +261,19 @@ mod test {
         );
     }
 
+    #[test]
+    fn big_integer() {
+        let s = Lexer::new(Rc::from("9223372036854775807 9223372036854775808"));
+        assert_eq!(
+            s.map(|t| t.kind).collect::<Vec<_>>(),
+            vec![
+                TokenType::Integer(i64::MAX),
+                TokenType::BigInteger(BigInt::parse("9223372036854775808").unwrap()),
+                TokenType::EOF,
+            ]
+        );
+    }
+
     #[test]
     fn comment() {
         let s = Lexer::new(Rc::from("if for while print fn # test comment\n\tread"));
@@ -226,4 +292,66 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn comment_pos() {
+        let code = "a = 1 # the end é\n#\n";
+        let tokens: Vec<_> = Lexer::new(Rc::from(code)).collect();
+        let comment = &tokens[3];
+        assert_eq!(comment.kind, TokenType::Comment("# the end é".to_string()));
+        assert_eq!(&code[comment.pos.start..comment.pos.end], "# the end é");
+        assert_eq!(tokens[5].kind, TokenType::Comment("#".to_string()));
+        assert_eq!(tokens[5].pos, Pos::new(19, 20));
+    }
+    #[test]
+    fn compound_bitwise() {
+        let s = Lexer::new(Rc::from("&= |= ^= <<= >>= & | && || << >> <= >="));
+        assert_eq!(
+            s.map(|t| t.kind).collect::<Vec<_>>(),
+            vec![
+                TokenType::AndEq,
+                TokenType::PipeEq,
+                TokenType::CaretEq,
+                TokenType::LessLessEq,
+                TokenType::GreaterGreaterEq,
+                TokenType::And,
+                TokenType::Pipe,
+                TokenType::AndAnd,
+                TokenType::PipePipe,
+                TokenType::LessLess,
+                TokenType::GreaterGreater,
+                TokenType::LessEq,
+                TokenType::GreaterEq,
+                TokenType::EOF,
+            ]
+        );
+    }
+    #[test]
+    fn colon() {
+        let s = Lexer::new(Rc::from("a:b :"));
+        assert_eq!(
+            s.map(|t| t.kind).collect::<Vec<_>>(),
+            vec![
+                TokenType::Identifier("a".to_string()),
+                TokenType::Colon,
+                TokenType::Identifier("b".to_string()),
+                TokenType::Colon,
+                TokenType::EOF,
+            ]
+        );
+    }
+    #[test]
+    fn hash_in_string() {
+        let s = Lexer::new(Rc::from("x = \"a # b\"# note"));
+        assert_eq!(
+            s.map(|t| t.kind).collect::<Vec<_>>(),
+            vec![
+                TokenType::Identifier("x".to_string()),
+                TokenType::Eq,
+                TokenType::String("a # b".to_string()),
+                TokenType::Comment("# note".to_string()),
+                TokenType::EOF,
+            ]
+        );
+    }
 }
@@ -7,13 +7,18 @@
 
 // Bytecode implementation
 pub mod aoc;
+pub mod bigint;
 pub mod bytecode;
+pub mod cache;
 pub mod error;
 pub mod expr;
+pub mod format;
 pub mod interpreter;
 pub mod lexer;
+pub mod native;
 pub mod parser;
 pub mod runtime;
+pub mod serialize;
 pub mod token;
 
 #[cfg(test)]
@@ -1,11 +1,21 @@
-use std::{fs, rc::Rc};
+use std::fs;
 
-use aoc_lang::aoc::{compile_and_run, debug_run};
+use aoc_lang::{
+    aoc::{check_named, compile_to_bytes, run_bytes, run_named, RunOptions},
+    cache::Cache,
+    expr::STD_PATH_VAR,
+};
 
 #[derive(Debug, Default)]
 struct Args {
     name: String,
     debug: bool,
+    profile: bool,
+    no_cache: bool,
+    strict: bool,
+    compile: bool,
+    check: bool,
+    std_path: Option<String>,
     version: bool,
     help: bool,
     fnames: Vec<String>,
@@ -15,9 +25,22 @@ fn parse_args() -> Args {
     let mut args = Args::default();
     let mut cli_args = std::env::args();
     args.name = cli_args.next().unwrap();
-    for arg in cli_args {
+    while let Some(arg) = cli_args.next() {
         match arg.as_str() {
             "--debug" | "-d" => args.debug = true,
+            "--profile" | "-p" => args.profile = true,
+            "--no-cache" => args.no_cache = true,
+            "--strict" | "-s" => args.strict = true,
+            "--compile" => args.compile = true,
+            "--check" | "-c" => args.check = true,
+            "--std" => match cli_args.next() {
+                Some(dir) if !dir.starts_with('-') => args.std_path = Some(dir),
+                _ => {
+                    eprintln!("--std expects a directory");
+                    print_usage(&args.name);
+                    std::process::exit(1);
+                }
+            },
             "--version" | "-v" => args.version = true,
             "--help" | "-h" => args.help = true,
             _ => args.fnames.push(arg),
@@ -25,6 +48,23 @@ fn parse_args() -> Args {
     }
     args
 }
+
+fn print_usage(name: &str) {
+    println!("Usage: {} [options] [file ...]", name);
+    println!("Options:");
+    println!("  -d, --debug     Run in debug mode");
+    println!("  -p, --profile   Print how often each operation ran to stderr");
+    println!("      --no-cache  Don't reuse or store compiled bytecode");
+    println!("  -s, --strict    Require declaring variables with let, fail on warnings and make print return nil");
+    println!(
+        "      --compile   Write the bytecode of each file to <file>.aocb instead of running it"
+    );
+    println!("  -c, --check     Only parse and compile each file, exit with an error if any of them fails");
+    println!("      --std DIR   Import DIR/std.aoc with use \"std\" instead of the bundled standard library (or set {STD_PATH_VAR})");
+    println!("  -v, --version   Print version and exit");
+    println!("  -h, --help      Print this help and exit");
+}
+
 fn main() {
     let args = parse_args();
     if args.version {
@@ -32,11 +72,7 @@ fn main() {
         return;
     }
     if args.help {
-        println!("Usage: {} [options] [file ...]", args.name);
-        println!("Options:");
-        println!("  -d, --debug     Run in debug mode");
-        println!("  -v, --version   Print version and exit");
-        println!("  -h, --help      Print this help and exit");
+        print_usage(&args.name);
         return;
     }
     if args.fnames.is_empty() {
@@ -46,13 +82,43 @@ fn main() {
         );
         return;
     }
-    let runner: fn(Rc<str>) -> aoc_lang::runtime::Value = if args.debug {
-        |code| debug_run(code, &mut std::io::stdout())
-    } else {
-        |code| compile_and_run(code, &mut std::io::stdout())
-    };
+    if let Some(dir) = &args.std_path {
+        std::env::set_var(STD_PATH_VAR, dir);
+    }
+    // The cache doesn't notice when a different standard library is used.
+    let no_cache = args.no_cache || std::env::var_os(STD_PATH_VAR).is_some();
+    let cache = Cache::new(Cache::default_dir());
+    let mut failed = false;
     for fname in &args.fnames {
+        if fname.ends_with(".aocb") {
+            let bytes = fs::read(fname).expect("File not found");
+            run_bytes(&bytes, &mut std::io::stdout());
+            continue;
+        }
         let code = fs::read_to_string(fname).expect("File not found");
-        runner(code.into());
+        let (name, code) = (Some(fname.as_str().into()), code.into());
+        let mut stdout = std::io::stdout();
+        if args.check {
+            failed |= !check_named(name, code, args.strict, &mut stdout);
+        } else if args.compile {
+            let out = std::path::Path::new(fname).with_extension("aocb");
+            if let Some(bytes) = compile_to_bytes(name, code, args.strict, &mut stdout) {
+                fs::write(out, bytes).expect("Cannot write bytecode");
+            }
+        } else {
+            let mut stderr = std::io::stderr();
+            let options = RunOptions {
+                strict: args.strict,
+                debug: args.debug,
+                profile: args
+                    .profile
+                    .then_some(&mut stderr as &mut dyn std::io::Write),
+                cache: (!no_cache).then_some(&cache),
+            };
+            run_named(name, code, options, &mut stdout);
+        }
+    }
+    if failed {
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,1247 @@
+use std::{
+    cell::RefCell,
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    io::Write,
+    rc::Rc,
+};
+
+use crate::{
+    error::{ErrorType, RuntimeError},
+    interpreter::{to_iter, Interpreter},
+    runtime::{obj_get, Iter, Object, Repr, Value},
+};
+
+type Error = crate::error::Error<RuntimeError>;
+type Result<T> = crate::error::Result<T, RuntimeError>;
+
+macro_rules! natives {
+    ($($variant:ident => $name:literal,)*) => {
+        /// Functions implemented in Rust that are available in every program.
+        /// A variable with the same name shadows the native.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Native {
+            $($variant,)*
+        }
+
+        impl Native {
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+natives! {
+    PrintSep => "print_sep",
+    GridGet => "grid_get",
+    GridSet => "grid_set",
+    Complex => "complex",
+    Real => "real",
+    Imag => "imag",
+    Frac => "frac",
+    Range => "range",
+    Lines => "lines",
+    FmtFloat => "fmt_float",
+    Hex => "hex",
+    Oct => "oct",
+    Bin => "bin",
+    Numbers => "numbers",
+    FindAll => "find_all",
+    Split => "split",
+    Join => "join",
+    ZipWith => "zip_with",
+    Zip => "zip",
+    Map => "map",
+    Filter => "filter",
+    Reduce => "reduce",
+    Unzip => "unzip",
+    Repr => "repr",
+    WrappingAdd => "wadd",
+    WrappingSub => "wsub",
+    WrappingMul => "wmul",
+    DefaultDict => "defaultdict",
+    AssertEq => "assert_eq",
+    Error => "error",
+    NewVec => "new_vec",
+    NewGrid => "new_grid",
+    Scan => "scan",
+    Partition => "partition",
+    CountIf => "count_if",
+    MaxBy => "max_by",
+    MinBy => "min_by",
+    Sort => "sort",
+    SortDesc => "sort_desc",
+    Union => "union",
+    Intersect => "intersect",
+    Diff => "diff",
+    RotateCw => "rotate_cw",
+    RotateCcw => "rotate_ccw",
+    FlipH => "flip_h",
+    FlipV => "flip_v",
+    Bfs => "bfs",
+    Dijkstra => "dijkstra",
+    Printf => "printf",
+    Insert => "insert",
+    RemoveAt => "remove_at",
+    Rotate => "rotate",
+    Swap => "swap",
+    Same => "same",
+    Len => "len",
+    Bytes => "bytes",
+    Decode => "decode",
+    Int => "int",
+    Float => "float",
+    Str => "str",
+    Type => "type",
+}
+
+/// Checks that a native got exactly `N` arguments.
+fn take_args<const N: usize>(native: Native, args: Vec<Value>) -> Result<[Value; N]> {
+    args.try_into().map_err(|args: Vec<Value>| {
+        Error::typed(
+            ErrorType::Call,
+            format!(
+                "{} expects {N} arguments, but got {}",
+                native.name(),
+                args.len()
+            ),
+        )
+    })
+}
+
+/// The start, end and step of `range(end)`, `range(start, end)` or
+/// `range(start, end, step)`.
+pub(crate) fn range_bounds(args: &[Value]) -> Result<(i64, i64, i64)> {
+    match args {
+        [Value::Int(end)] => Ok((0, *end, 1)),
+        [Value::Int(start), Value::Int(end)] => Ok((*start, *end, 1)),
+        [Value::Int(start), Value::Int(end), Value::Int(step)] if *step != 0 => {
+            Ok((*start, *end, *step))
+        }
+        _ => {
+            let args: Vec<_> = args.iter().map(Value::to_string).collect();
+            Err(Error::typed(
+                ErrorType::Type,
+                format!(
+                    "range expects 1 to 3 integers and a non zero step, not ({})",
+                    args.join(", ")
+                ),
+            ))
+        }
+    }
+}
+
+/// Checks that a native got a function it can call with `arity` arguments.
+/// Natives take any number of arguments, so they're only checked when called.
+fn callback(native: Native, f: &Value, arity: usize) -> Result<()> {
+    match f {
+        Value::Fn { num_params, .. } if *num_params == arity => Ok(()),
+        Value::Native(_) => Ok(()),
+        Value::Fn { num_params, .. } => Err(Error::typed(
+            ErrorType::Call,
+            format!(
+                "{} expects a function of {arity} arguments, not {num_params}",
+                native.name()
+            ),
+        )),
+        f => Err(Error::typed(
+            ErrorType::Type,
+            format!("{} expects a function, not {f}", native.name()),
+        )),
+    }
+}
+
+/// Checks that a native got a non negative integer for the length of a vector.
+fn vec_len(native: Native, n: &Value) -> Result<usize> {
+    match n {
+        Value::Int(n @ 0..) => Ok(*n as usize),
+        Value::Int(n) => Err(Error::typed(
+            ErrorType::Value,
+            format!("{} expects a non negative length, not {n}", native.name()),
+        )),
+        n => Err(Error::typed(
+            ErrorType::Type,
+            format!("{} expects an integer length, not {n}", native.name()),
+        )),
+    }
+}
+
+/// A stable merge sort that stops at the first failed comparison. Unlike
+/// `sort_by` it doesn't mind comparators that aren't a total order.
+fn merge_sort(
+    mut items: Vec<Value>,
+    cmp: &mut dyn FnMut(&Value, &Value) -> Result<Ordering>,
+) -> Result<Vec<Value>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, cmp)?;
+    let mut right = merge_sort(right, cmp)?.into_iter().peekable();
+    let mut result = Vec::with_capacity(left.len() + right.len());
+    for x in left {
+        while let Some(y) = right.peek() {
+            if cmp(y, &x)? != Ordering::Less {
+                break;
+            }
+            result.extend(right.next());
+        }
+        result.push(x);
+    }
+    result.extend(right);
+    Ok(result)
+}
+
+/// Copies the rows of a grid, which has to be a vector of equally long
+/// vectors.
+fn grid_rows(native: Native, grid: &Value) -> Result<Vec<Vec<Value>>> {
+    let err = |ty, msg: &str| {
+        Err(Error::typed(
+            ty,
+            format!("{} expects {msg}, not {grid}", native.name()),
+        ))
+    };
+    let Value::Vec(rows) = grid else {
+        return err(ErrorType::Type, "a vector of rows");
+    };
+    let mut result = Vec::with_capacity(rows.borrow().len());
+    for row in rows.borrow().iter() {
+        let Value::Vec(row) = row else {
+            return err(ErrorType::Type, "a vector of rows");
+        };
+        result.push(row.borrow().clone());
+    }
+    if result.iter().any(|row| row.len() != result[0].len()) {
+        return err(ErrorType::Value, "rows of the same length");
+    }
+    Ok(result)
+}
+
+/// Replaces the `%d`, `%s`, `%f` and `%x` placeholders of `fmt` with the
+/// arguments. `%.2f` prints a number with 2 decimals and `%%` a percent sign.
+fn format(fmt: &str, args: &[Value]) -> Result<String> {
+    let type_err = |spec: &str, arg: &Value| {
+        let expected = if spec.ends_with('f') {
+            "a number"
+        } else {
+            "an integer"
+        };
+        Err(Error::typed(
+            ErrorType::Type,
+            format!("printf %{spec} expects {expected}, not {arg}"),
+        ))
+    };
+    let mut result = String::new();
+    let mut args = args.iter();
+    let mut used = 0;
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        let mut spec = String::new();
+        for c in chars.by_ref() {
+            spec.push(c);
+            if !matches!(c, '.' | '0'..='9') {
+                break;
+            }
+        }
+        if spec == "%" {
+            result.push('%');
+            continue;
+        }
+        let arg = args.next().ok_or_else(|| {
+            Error::typed(
+                ErrorType::Call,
+                format!("printf format {fmt:?} needs more than {used} arguments"),
+            )
+        })?;
+        used += 1;
+        match (spec.as_str(), arg) {
+            ("s", arg) => result += &arg.to_string(),
+            ("d", Value::Int(_) | Value::BigInt(_)) => result += &arg.to_string(),
+            ("x", Value::Int(n)) => {
+                let sign = if *n < 0 { "-" } else { "" };
+                result += &format!("{sign}{:x}", n.unsigned_abs());
+            }
+            ("d" | "x", arg) => return type_err(&spec, arg),
+            ("f", arg) => match arg.as_float() {
+                Some(x) => result += &x.to_string(),
+                None => return type_err(&spec, arg),
+            },
+            (spec, arg) if spec.starts_with('.') && spec.ends_with('f') => {
+                let Ok(decimals) = spec[1..spec.len() - 1].parse::<usize>() else {
+                    return Err(Error::typed(
+                        ErrorType::Value,
+                        format!("Unknown printf placeholder %{spec}"),
+                    ));
+                };
+                match arg.as_float() {
+                    Some(x) => result += &format!("{x:.decimals$}"),
+                    None => return type_err(spec, arg),
+                }
+            }
+            (spec, _) => {
+                return Err(Error::typed(
+                    ErrorType::Value,
+                    format!("Unknown printf placeholder %{spec}"),
+                ))
+            }
+        }
+    }
+    if args.next().is_some() {
+        return Err(Error::typed(
+            ErrorType::Call,
+            format!(
+                "printf format {fmt:?} expects {used} arguments, but got {}",
+                used + 1 + args.count()
+            ),
+        ));
+    }
+    Ok(result)
+}
+
+/// Converts an index into a position in a vector of length `len`, counting
+/// from the end if it's negative. With `end` set the position right after the
+/// last element is allowed too.
+fn vec_index(native: Native, idx: &Value, len: usize, end: bool) -> Result<usize> {
+    let Value::Int(i) = idx else {
+        return Err(Error::typed(
+            ErrorType::Type,
+            format!("{} expects an integer index, not {idx}", native.name()),
+        ));
+    };
+    let pos = if *i < 0 {
+        len.checked_sub(i.unsigned_abs() as usize)
+    } else {
+        Some(*i as usize)
+    };
+    match pos {
+        Some(pos) if pos < len || (end && pos == len) => Ok(pos),
+        _ => Err(Error::typed(
+            ErrorType::Index,
+            format!("Index {i} out of range for vector of length {len}"),
+        )),
+    }
+}
+
+/// Packs grid coordinates into a single integer key, which is much cheaper to
+/// hash than a `[x, y]` vector.
+fn grid_key(x: &Value, y: &Value) -> Result<Value> {
+    match (x, y) {
+        (Value::Int(x), Value::Int(y)) => match (i32::try_from(*x), i32::try_from(*y)) {
+            (Ok(x), Ok(y)) => Ok(Value::Int(((x as i64) << 32) | (y as u32 as i64))),
+            _ => Err(Error::typed(
+                ErrorType::Value,
+                format!("Grid coordinates {x}, {y} don't fit into 32 bits"),
+            )),
+        },
+        (x, y) => Err(Error::typed(
+            ErrorType::Type,
+            format!("Grid coordinates must be integers, not {x} and {y}"),
+        )),
+    }
+}
+
+/// Extracts all numbers from a string. Unless `unsigned` is set a `-` right in
+/// front of a number makes it negative.
+fn numbers(s: &str, unsigned: bool, floats: bool) -> Result<Vec<Value>> {
+    let bytes = s.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let mut start = i;
+        if !unsigned && start > 0 && bytes[start - 1] == b'-' {
+            start -= 1;
+        }
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        if floats && i + 1 < bytes.len() && bytes[i] == b'.' && bytes[i + 1].is_ascii_digit() {
+            i += 1;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+        let num = &s[start..i];
+        result.push(if floats {
+            Value::Float(num.parse().expect("only digits, a sign and a dot"))
+        } else {
+            Value::Int(num.parse().map_err(|_| {
+                Error::typed(
+                    ErrorType::Value,
+                    format!("Number {num} doesn't fit into 64 bits"),
+                )
+            })?)
+        });
+    }
+    Ok(result)
+}
+
+impl<W: Write> Interpreter<W> {
+    pub(crate) fn call_native(&mut self, native: Native, args: Vec<Value>) -> Result<Value> {
+        match native {
+            Native::PrintSep => self.print_sep(args),
+            Native::GridGet => {
+                let [obj, x, y] = take_args(native, args)?;
+                let Value::Obj(obj) = obj else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("grid_get expects an object, not {obj}"),
+                    ));
+                };
+                let val = obj_get(&mut obj.borrow_mut(), &grid_key(&x, &y)?);
+                Ok(val)
+            }
+            Native::GridSet => {
+                let [obj, x, y, val] = take_args(native, args)?;
+                let Value::Obj(obj) = obj else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("grid_set expects an object, not {obj}"),
+                    ));
+                };
+                obj.borrow_mut().insert(grid_key(&x, &y)?, val.clone());
+                Ok(val)
+            }
+            Native::Complex => match take_args(native, args)? {
+                [re @ (Value::Int(_) | Value::Float(_)), im @ (Value::Int(_) | Value::Float(_))] => {
+                    Ok(Value::Complex(
+                        re.as_complex().unwrap().0,
+                        im.as_complex().unwrap().0,
+                    ))
+                }
+                [re, im] => Err(Error::typed(
+                    ErrorType::Type,
+                    format!("complex expects two numbers, not {re} and {im}"),
+                )),
+            },
+            Native::Frac => match take_args(native, args)? {
+                [Value::Int(num), Value::Int(den)] => Value::rational(num as i128, den as i128)
+                    .map_err(|e| {
+                        let ty = if den == 0 {
+                            ErrorType::ZeroDivision
+                        } else {
+                            ErrorType::Value
+                        };
+                        Error::typed(ty, e)
+                    }),
+                [num, den] => Err(Error::typed(
+                    ErrorType::Type,
+                    format!("frac expects two integers, not {num} and {den}"),
+                )),
+            },
+            Native::Range => {
+                let (next, end, step) = range_bounds(&args)?;
+                let items = self.collect(Iter::Range { next, end, step }.into())?;
+                Ok(Value::Vec(Rc::new(RefCell::new(items))))
+            }
+            Native::Lines => {
+                let [] = take_args(native, args)?;
+                Ok(Iter::Lines.into())
+            }
+            Native::FmtFloat => {
+                let [x, decimals] = take_args(native, args)?;
+                let (Some(x), Value::Int(decimals @ 0..)) = (x.as_float(), &decimals) else {
+                    return Err(Error::typed(ErrorType::Type, format!(
+                        "fmt_float expects a number and a non negative number of decimals, not {x} and {decimals}"
+                    )));
+                };
+                let mut s = format!("{x:.*}", *decimals as usize);
+                // Don't print -0.00 for small negative numbers that round to zero.
+                if s.starts_with('-') && s.bytes().all(|c| matches!(c, b'-' | b'0' | b'.')) {
+                    s.remove(0);
+                }
+                Ok(Value::Str(Rc::new(s)))
+            }
+            Native::Hex | Native::Oct | Native::Bin => {
+                let [n] = take_args(native, args)?;
+                let Value::Int(n) = n else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("{} expects an integer, not {n}", native.name()),
+                    ));
+                };
+                let sign = if n < 0 { "-" } else { "" };
+                let digits = match native {
+                    Native::Hex => format!("{:x}", n.unsigned_abs()),
+                    Native::Oct => format!("{:o}", n.unsigned_abs()),
+                    _ => format!("{:b}", n.unsigned_abs()),
+                };
+                Ok(Value::Str(Rc::new(format!("{sign}{digits}"))))
+            }
+            Native::Numbers => {
+                let (s, flags) = match args.as_slice() {
+                    [Value::Str(s)] => (s, ""),
+                    [Value::Str(s), Value::Str(flags)] => (s, flags.as_str()),
+                    _ => {
+                        let args: Vec<_> = args.iter().map(Value::to_string).collect();
+                        return Err(Error::typed(
+                            ErrorType::Type,
+                            format!(
+                                "numbers expects a string and optional flags, not ({})",
+                                args.join(", ")
+                            ),
+                        ));
+                    }
+                };
+                if let Some(c) = flags.chars().find(|c| !matches!(c, 'u' | 'f')) {
+                    return Err(Error::typed(
+                        ErrorType::Value,
+                        format!("Unknown numbers flag {c:?}, expected 'u' or 'f'"),
+                    ));
+                }
+                let nums = numbers(s, flags.contains('u'), flags.contains('f'))?;
+                Ok(Value::Vec(Rc::new(RefCell::new(nums))))
+            }
+            Native::FindAll => {
+                let [s, needle] = take_args(native, args)?;
+                let (Value::Str(s), Value::Str(needle)) = (&s, &needle) else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("find_all expects two strings, not {s} and {needle}"),
+                    ));
+                };
+                // Every position would match, which is never what you want.
+                if needle.is_empty() {
+                    return Err(Error::typed(
+                        ErrorType::Value,
+                        "find_all cannot search for an empty string".to_string(),
+                    ));
+                }
+                let idx = s
+                    .match_indices(needle.as_str())
+                    .map(|(i, _)| Value::Int(i as i64))
+                    .collect();
+                Ok(Value::Vec(Rc::new(RefCell::new(idx))))
+            }
+            Native::Split => {
+                let [s, sep] = take_args(native, args)?;
+                let (Value::Str(s), Value::Str(sep)) = (&s, &sep) else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("split expects two strings, not {s} and {sep}"),
+                    ));
+                };
+                let str_value = |s: &str| Value::Str(Rc::new(s.to_string()));
+                // An empty separator splits into single characters.
+                let parts = if sep.is_empty() {
+                    s.chars()
+                        .map(|c| str_value(c.encode_utf8(&mut [0; 4])))
+                        .collect()
+                } else {
+                    s.split(sep.as_str()).map(str_value).collect()
+                };
+                Ok(Value::Vec(Rc::new(RefCell::new(parts))))
+            }
+            Native::Join => {
+                let [vec, sep] = take_args(native, args)?;
+                let (Value::Vec(vec), Value::Str(sep)) = (&vec, &sep) else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("join expects a vector and a string, not {vec} and {sep}"),
+                    ));
+                };
+                let mut result = String::new();
+                for (i, v) in vec.borrow().iter().enumerate() {
+                    let Value::Str(s) = v else {
+                        return Err(Error::typed(
+                            ErrorType::Type,
+                            format!("join expects a vector of strings, but element {i} is {v}"),
+                        ));
+                    };
+                    if i > 0 {
+                        result += sep;
+                    }
+                    result += s;
+                }
+                Ok(Value::Str(Rc::new(result)))
+            }
+            Native::ZipWith => {
+                let [a, b, f] = take_args(native, args)?;
+                let (Value::Vec(a), Value::Vec(b)) = (&a, &b) else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("zip_with expects two vectors, not {a} and {b}"),
+                    ));
+                };
+                // Copy the elements so `f` can modify the vectors.
+                let pairs: Vec<_> = a
+                    .borrow()
+                    .iter()
+                    .cloned()
+                    .zip(b.borrow().iter().cloned())
+                    .collect();
+                let result = pairs
+                    .into_iter()
+                    .map(|(x, y)| self.call(f.clone(), vec![x, y]))
+                    .collect::<Result<_>>()?;
+                Ok(Value::Vec(Rc::new(RefCell::new(result))))
+            }
+            Native::Zip => {
+                let [a, b] = take_args(native, args)?;
+                let (Value::Vec(a), Value::Vec(b)) = (&a, &b) else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("zip expects two vectors, not {a} and {b}"),
+                    ));
+                };
+                let result = a
+                    .borrow()
+                    .iter()
+                    .zip(b.borrow().iter())
+                    .map(|(x, y)| Value::Vec(Rc::new(RefCell::new(vec![x.clone(), y.clone()]))))
+                    .collect();
+                Ok(Value::Vec(Rc::new(RefCell::new(result))))
+            }
+            Native::Map | Native::Filter => {
+                let [vals, f] = take_args(native, args)?;
+                callback(native, &f, 1)?;
+                let mut result = Vec::new();
+                for x in self.collect(vals)? {
+                    match native {
+                        Native::Map => result.push(self.call(f.clone(), vec![x])?),
+                        _ if self.call(f.clone(), vec![x.clone()])?.truthy() => result.push(x),
+                        _ => {}
+                    }
+                }
+                Ok(Value::Vec(Rc::new(RefCell::new(result))))
+            }
+            Native::Reduce => {
+                let [vals, f, init] = take_args(native, args)?;
+                callback(native, &f, 2)?;
+                let mut acc = init;
+                for x in self.collect(vals)? {
+                    acc = self.call(f.clone(), vec![acc, x])?;
+                }
+                Ok(acc)
+            }
+            Native::Unzip => {
+                let [vec] = take_args(native, args)?;
+                let Value::Vec(vec) = &vec else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("unzip expects a vector of pairs, not {vec}"),
+                    ));
+                };
+                let (mut firsts, mut seconds) = (Vec::new(), Vec::new());
+                for (i, pair) in vec.borrow().iter().enumerate() {
+                    let pair_items = match pair {
+                        Value::Vec(p) => p.borrow().clone(),
+                        _ => Vec::new(),
+                    };
+                    let [x, y] = <[Value; 2]>::try_from(pair_items).map_err(|_| {
+                        Error::typed(
+                            ErrorType::Value,
+                            format!("unzip expects a vector of pairs, but element {i} is {pair}"),
+                        )
+                    })?;
+                    firsts.push(x);
+                    seconds.push(y);
+                }
+                let firsts = Value::Vec(Rc::new(RefCell::new(firsts)));
+                let seconds = Value::Vec(Rc::new(RefCell::new(seconds)));
+                Ok(Value::Vec(Rc::new(RefCell::new(vec![firsts, seconds]))))
+            }
+            Native::Scan => {
+                let [vec, init, f] = take_args(native, args)?;
+                let Value::Vec(vec) = &vec else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("scan expects a vector, not {vec}"),
+                    ));
+                };
+                // Copy the elements so `f` can modify the vector.
+                let items: Vec<_> = vec.borrow().iter().cloned().collect();
+                let mut result = Vec::with_capacity(items.len() + 1);
+                result.push(init);
+                for x in items {
+                    let acc = result.last().unwrap().clone();
+                    result.push(self.call(f.clone(), vec![acc, x])?);
+                }
+                Ok(Value::Vec(Rc::new(RefCell::new(result))))
+            }
+            Native::Partition => {
+                let [vec, pred] = take_args(native, args)?;
+                let Value::Vec(vec) = &vec else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("partition expects a vector, not {vec}"),
+                    ));
+                };
+                let items: Vec<_> = vec.borrow().iter().cloned().collect();
+                let (mut yes, mut no) = (Vec::new(), Vec::new());
+                for x in items {
+                    if self.call(pred.clone(), vec![x.clone()])?.truthy() {
+                        yes.push(x);
+                    } else {
+                        no.push(x);
+                    }
+                }
+                let yes = Value::Vec(Rc::new(RefCell::new(yes)));
+                let no = Value::Vec(Rc::new(RefCell::new(no)));
+                Ok(Value::Vec(Rc::new(RefCell::new(vec![yes, no]))))
+            }
+            Native::CountIf => {
+                let [vec, pred] = take_args(native, args)?;
+                let Value::Vec(vec) = &vec else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("count_if expects a vector, not {vec}"),
+                    ));
+                };
+                let items: Vec<_> = vec.borrow().iter().cloned().collect();
+                let mut count = 0;
+                for x in items {
+                    if self.call(pred.clone(), vec![x])?.truthy() {
+                        count += 1;
+                    }
+                }
+                Ok(Value::Int(count))
+            }
+            // Ties keep the first element with the best key.
+            Native::MaxBy | Native::MinBy => {
+                let [vec, key] = take_args(native, args)?;
+                let Value::Vec(vec) = &vec else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("{} expects a vector, not {vec}", native.name()),
+                    ));
+                };
+                callback(native, &key, 1)?;
+                let items: Vec<_> = vec.borrow().iter().cloned().collect();
+                let better = if native == Native::MaxBy {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                };
+                let mut best: Option<(Value, Value)> = None;
+                for x in items {
+                    let k = self.call(key.clone(), vec![x.clone()])?;
+                    if let Some((_, best_key)) = &best {
+                        let ord = k.partial_cmp(best_key).ok_or_else(|| {
+                            Error::typed(
+                                ErrorType::Type,
+                                format!("Cannot compare {k} and {best_key}"),
+                            )
+                        })?;
+                        if ord != better {
+                            continue;
+                        }
+                    }
+                    best = Some((x, k));
+                }
+                let Some((x, k)) = best else {
+                    return Err(Error::typed(
+                        ErrorType::Value,
+                        format!("{} of an empty vector", native.name()),
+                    ));
+                };
+                Ok(Value::Vec(Rc::new(RefCell::new(vec![x, k]))))
+            }
+            Native::Sort | Native::SortDesc => {
+                let (vec, cmp) = match <[Value; 2]>::try_from(args) {
+                    Ok([vec, cmp]) => (vec, Some(cmp)),
+                    Err(args) => (take_args::<1>(native, args)?[0].clone(), None),
+                };
+                self.sorted(native, &vec, cmp, native == Native::SortDesc)
+            }
+            // Vectors are used as sets: the result has no duplicates and keeps
+            // the elements in the order they first appear in.
+            Native::Union | Native::Intersect | Native::Diff => {
+                let [a, b] = take_args(native, args)?;
+                let (Value::Vec(a), Value::Vec(b)) = (&a, &b) else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("{} expects two vectors, not {a} and {b}", native.name()),
+                    ));
+                };
+                #[allow(clippy::mutable_key_type)]
+                let in_b: HashSet<_> = b.borrow().iter().cloned().collect();
+                #[allow(clippy::mutable_key_type)]
+                let mut seen = HashSet::new();
+                let mut result = Vec::new();
+                let mut add = |x: &Value, keep: bool| {
+                    if keep && seen.insert(x.clone()) {
+                        result.push(x.clone());
+                    }
+                };
+                for x in a.borrow().iter() {
+                    match native {
+                        Native::Intersect => add(x, in_b.contains(x)),
+                        Native::Diff => add(x, !in_b.contains(x)),
+                        _ => add(x, true),
+                    }
+                }
+                if native == Native::Union {
+                    b.borrow().iter().for_each(|x| add(x, true));
+                }
+                Ok(Value::Vec(Rc::new(RefCell::new(result))))
+            }
+            Native::RotateCw | Native::RotateCcw | Native::FlipH | Native::FlipV => {
+                let [grid] = take_args(native, args)?;
+                let mut rows = grid_rows(native, &grid)?;
+                let cols = rows.first().map_or(0, Vec::len);
+                let rows = match native {
+                    Native::RotateCw => (0..cols)
+                        .map(|c| rows.iter().rev().map(|row| row[c].clone()).collect())
+                        .collect(),
+                    Native::RotateCcw => (0..cols)
+                        .rev()
+                        .map(|c| rows.iter().map(|row| row[c].clone()).collect())
+                        .collect(),
+                    Native::FlipH => {
+                        rows.iter_mut().for_each(|row| row.reverse());
+                        rows
+                    }
+                    _ => {
+                        rows.reverse();
+                        rows
+                    }
+                };
+                let rows = rows
+                    .into_iter()
+                    .map(|row| Value::Vec(Rc::new(RefCell::new(row))))
+                    .collect();
+                Ok(Value::Vec(Rc::new(RefCell::new(rows))))
+            }
+            Native::Bfs => {
+                let [start, neighbors] = take_args(native, args)?;
+                #[allow(clippy::mutable_key_type)]
+                let mut dist = HashMap::from([(start.clone(), Value::Int(0))]);
+                let mut queue = VecDeque::from([(start, 0)]);
+                while let Some((node, d)) = queue.pop_front() {
+                    let next = self.call(neighbors.clone(), vec![node])?;
+                    let Value::Vec(next) = next else {
+                        return Err(Error::typed(
+                            ErrorType::Type,
+                            format!("bfs neighbors function has to return a vector, not {next}"),
+                        ));
+                    };
+                    for n in next.borrow().iter() {
+                        if !dist.contains_key(n) {
+                            dist.insert(n.clone(), Value::Int(d + 1));
+                            queue.push_back((n.clone(), d + 1));
+                        }
+                    }
+                }
+                Ok(Value::Obj(Rc::new(RefCell::new(dist.into()))))
+            }
+            Native::Dijkstra => {
+                let [start, neighbors] = take_args(native, args)?;
+                #[allow(clippy::mutable_key_type)]
+                let mut dist = HashMap::from([(start.clone(), 0i64)]);
+                // Values can't be ordered, so the heap refers to nodes by index.
+                let mut nodes = vec![start];
+                let mut heap = BinaryHeap::from([Reverse((0, 0))]);
+                while let Some(Reverse((d, idx))) = heap.pop() {
+                    let node = nodes[idx].clone();
+                    if dist[&node] < d {
+                        continue;
+                    }
+                    let next = self.call(neighbors.clone(), vec![node])?;
+                    let Value::Vec(next) = next else {
+                        return Err(Error::typed(
+                            ErrorType::Type,
+                            format!(
+                                "dijkstra neighbors function has to return a vector, not {next}"
+                            ),
+                        ));
+                    };
+                    for edge in next.borrow().iter() {
+                        let pair = match edge {
+                            Value::Vec(edge) => match edge.borrow().as_slice() {
+                                [n, Value::Int(cost @ 0..)] => Some((n.clone(), *cost)),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        let Some((n, cost)) = pair else {
+                            return Err(Error::typed(
+                                ErrorType::Value,
+                                format!("dijkstra expects [node, cost] pairs with a non negative integer cost, not {edge}"),
+                            ));
+                        };
+                        let nd = d.checked_add(cost).ok_or_else(|| {
+                            Error::typed(
+                                ErrorType::Value,
+                                "dijkstra distance doesn't fit into 64 bits".to_string(),
+                            )
+                        })?;
+                        if dist.get(&n).is_some_and(|&old| old <= nd) {
+                            continue;
+                        }
+                        dist.insert(n.clone(), nd);
+                        nodes.push(n);
+                        heap.push(Reverse((nd, nodes.len() - 1)));
+                    }
+                }
+                #[allow(clippy::mutable_key_type)]
+                let dist: HashMap<_, _> =
+                    dist.into_iter().map(|(k, d)| (k, Value::Int(d))).collect();
+                Ok(Value::Obj(Rc::new(RefCell::new(dist.into()))))
+            }
+            Native::Printf => {
+                let Some(Value::Str(fmt)) = args.first() else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        "printf expects a format string as the first argument".to_string(),
+                    ));
+                };
+                let s = format(fmt, &args[1..])?;
+                let output = self.output.as_mut().unwrap();
+                write!(output, "{s}").map_err(Error::from)?;
+                Ok(Value::Str(Rc::new(s)))
+            }
+            Native::Insert | Native::RemoveAt => {
+                let (vec, idx, val) = match args.as_slice() {
+                    [Value::Vec(vec), idx, val] if native == Native::Insert => {
+                        (vec, idx, Some(val))
+                    }
+                    [Value::Vec(vec), idx] if native == Native::RemoveAt => (vec, idx, None),
+                    _ => {
+                        let args: Vec<_> = args.iter().map(Value::to_string).collect();
+                        let expected = match native {
+                            Native::Insert => "a vector, an index and a value",
+                            _ => "a vector and an index",
+                        };
+                        return Err(Error::typed(
+                            ErrorType::Type,
+                            format!(
+                                "{} expects {expected}, not ({})",
+                                native.name(),
+                                args.join(", ")
+                            ),
+                        ));
+                    }
+                };
+                let mut vec = vec.borrow_mut();
+                let pos = vec_index(native, idx, vec.len(), val.is_some())?;
+                match val {
+                    Some(val) => {
+                        vec.insert(pos, val.clone());
+                        Ok(val.clone())
+                    }
+                    None => Ok(vec.remove(pos)),
+                }
+            }
+            Native::Rotate => {
+                let [vec, k] = take_args(native, args)?;
+                let (Value::Vec(vec), Value::Int(k)) = (&vec, &k) else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("rotate expects a vector and an integer, not {vec} and {k}"),
+                    ));
+                };
+                let mut vec = vec.borrow().clone();
+                if !vec.is_empty() {
+                    let k = k.rem_euclid(vec.len() as i64) as usize;
+                    vec.rotate_left(k);
+                }
+                Ok(Value::Vec(Rc::new(RefCell::new(vec))))
+            }
+            Native::Swap => {
+                let [vec, i, j] = take_args(native, args)?;
+                let Value::Vec(v) = &vec else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("swap expects a vector, not {vec}"),
+                    ));
+                };
+                {
+                    let mut v = v.borrow_mut();
+                    let i = vec_index(native, &i, v.len(), false)?;
+                    let j = vec_index(native, &j, v.len(), false)?;
+                    v.swap(i, j);
+                }
+                Ok(vec)
+            }
+            Native::Len => {
+                let [val] = take_args(native, args)?;
+                let len = match &val {
+                    Value::Str(s) => s.len(),
+                    Value::Bytes(b) => b.len(),
+                    Value::Vec(v) => v.borrow().len(),
+                    Value::Obj(o) => o.borrow().len(),
+                    _ => {
+                        return Err(Error::typed(
+                            ErrorType::Type,
+                            format!("len expects a string, bytes, vector or object, not {val}"),
+                        ))
+                    }
+                };
+                Ok(Value::Int(len as i64))
+            }
+            Native::Bytes => {
+                let [val] = take_args(native, args)?;
+                let bytes = match &val {
+                    Value::Str(s) => s.as_bytes().to_vec(),
+                    Value::Bytes(b) => return Ok(Value::Bytes(b.clone())),
+                    Value::Vec(v) => v
+                        .borrow()
+                        .iter()
+                        .map(|c| match c {
+                            Value::Int(c @ 0..=255) => Ok(*c as u8),
+                            c => Err(Error::typed(
+                                ErrorType::Value,
+                                format!("bytes expects integers from 0 to 255, not {c}"),
+                            )),
+                        })
+                        .collect::<Result<_>>()?,
+                    _ => {
+                        return Err(Error::typed(
+                            ErrorType::Type,
+                            format!("bytes expects a string or a vector, not {val}"),
+                        ))
+                    }
+                };
+                Ok(Value::Bytes(Rc::new(bytes)))
+            }
+            Native::Decode => {
+                let [val] = take_args(native, args)?;
+                let Value::Bytes(b) = &val else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("decode expects bytes, not {val}"),
+                    ));
+                };
+                let s = String::from_utf8(b.to_vec()).map_err(|e| {
+                    Error::typed(ErrorType::Value, format!("decode got invalid UTF-8: {e}"))
+                })?;
+                Ok(Value::Str(Rc::new(s)))
+            }
+            Native::Int => {
+                let [val] = take_args(native, args)?;
+                match &val {
+                    Value::Int(_) | Value::BigInt(_) => Ok(val),
+                    Value::Bool(b) => Ok(Value::Int(*b as i64)),
+                    Value::Rational(num, den) => Ok(Value::Int(num / den)),
+                    // Casting saturates, so check the range before truncating.
+                    Value::Float(f) if f.is_finite() && f.abs() < i64::MAX as f64 => {
+                        Ok(Value::Int(*f as i64))
+                    }
+                    Value::Str(s) => s.trim().parse().map(Value::Int).map_err(|_| {
+                        Error::typed(ErrorType::Value, format!("Cannot convert {s:?} to int"))
+                    }),
+                    Value::Float(_) => Err(Error::typed(
+                        ErrorType::Value,
+                        format!("Cannot convert {val} to int"),
+                    )),
+                    _ => Err(Error::typed(
+                        ErrorType::Type,
+                        format!("int expects a number or a string, not {val}"),
+                    )),
+                }
+            }
+            Native::Float => {
+                let [val] = take_args(native, args)?;
+                let f = match &val {
+                    Value::Float(f) => *f,
+                    Value::Int(n) => *n as f64,
+                    Value::Bool(b) => *b as i64 as f64,
+                    Value::Rational(num, den) => *num as f64 / *den as f64,
+                    Value::BigInt(n) => n.to_f64(),
+                    Value::Str(s) => s.trim().parse().map_err(|_| {
+                        Error::typed(ErrorType::Value, format!("Cannot convert {s:?} to float"))
+                    })?,
+                    _ => {
+                        return Err(Error::typed(
+                            ErrorType::Type,
+                            format!("float expects a number or a string, not {val}"),
+                        ))
+                    }
+                };
+                Ok(Value::Float(f))
+            }
+            Native::Str => {
+                let [val] = take_args(native, args)?;
+                match val {
+                    Value::Str(_) => Ok(val),
+                    _ => Ok(Value::Str(Rc::new(val.to_string()))),
+                }
+            }
+            Native::Type => {
+                let [val] = take_args(native, args)?;
+                Ok(Value::Str(Rc::new(val.type_name().to_string())))
+            }
+            // Values that aren't shared are the same when they're equal.
+            Native::Same => {
+                let same = match take_args(native, args)? {
+                    [Value::Vec(a), Value::Vec(b)] => Rc::ptr_eq(&a, &b),
+                    [Value::Obj(a), Value::Obj(b)] => Rc::ptr_eq(&a, &b),
+                    [Value::Str(a), Value::Str(b)] => Rc::ptr_eq(&a, &b),
+                    [Value::Bytes(a), Value::Bytes(b)] => Rc::ptr_eq(&a, &b),
+                    [Value::Ref(a), Value::Ref(b)] => Rc::ptr_eq(&a, &b),
+                    [Value::Vec(_)
+                    | Value::Obj(_)
+                    | Value::Str(_)
+                    | Value::Bytes(_)
+                    | Value::Ref(_), _]
+                    | [_, Value::Vec(_)
+                    | Value::Obj(_)
+                    | Value::Str(_)
+                    | Value::Bytes(_)
+                    | Value::Ref(_)] => false,
+                    [a, b] => a == b,
+                };
+                Ok(Value::Bool(same))
+            }
+            Native::Repr => {
+                let [x] = take_args(native, args)?;
+                Ok(Value::Str(Rc::new(Repr(&x).to_string())))
+            }
+            Native::WrappingAdd | Native::WrappingSub | Native::WrappingMul => {
+                let [a, b] = take_args(native, args)?;
+                let (Value::Int(a), Value::Int(b)) = (&a, &b) else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("{} expects two integers, not {a} and {b}", native.name()),
+                    ));
+                };
+                Ok(Value::Int(match native {
+                    Native::WrappingAdd => a.wrapping_add(*b),
+                    Native::WrappingSub => a.wrapping_sub(*b),
+                    _ => a.wrapping_mul(*b),
+                }))
+            }
+            Native::DefaultDict => {
+                let [default] = take_args(native, args)?;
+                let obj = Object {
+                    fields: HashMap::new(),
+                    default: Some(default),
+                };
+                Ok(Value::Obj(Rc::new(RefCell::new(obj))))
+            }
+            Native::AssertEq => {
+                let [a, b] = take_args(native, args)?;
+                if a != b {
+                    return Err(Error::typed(
+                        ErrorType::Assert,
+                        format!("assert_eq failed: {} != {}", Repr(&a), Repr(&b)),
+                    ));
+                }
+                Ok(Value::Nil)
+            }
+            Native::Error => {
+                let [msg] = take_args(native, args)?;
+                Err(Error::typed(ErrorType::User, msg.to_string()))
+            }
+            // Every cell gets its own copy, so that changing one doesn't
+            // change the others.
+            Native::NewVec => {
+                let [n, val] = take_args(native, args)?;
+                let vec = (0..vec_len(native, &n)?)
+                    .map(|_| val.deep_clone())
+                    .collect();
+                Ok(Value::Vec(Rc::new(RefCell::new(vec))))
+            }
+            Native::NewGrid => {
+                let [rows, cols, val] = take_args(native, args)?;
+                let cols = vec_len(native, &cols)?;
+                let grid = (0..vec_len(native, &rows)?)
+                    .map(|_| {
+                        let row = (0..cols).map(|_| val.deep_clone()).collect();
+                        Value::Vec(Rc::new(RefCell::new(row)))
+                    })
+                    .collect();
+                Ok(Value::Vec(Rc::new(RefCell::new(grid))))
+            }
+            Native::Real | Native::Imag => {
+                let [z] = take_args(native, args)?;
+                let Some((re, im)) = z.as_complex() else {
+                    return Err(Error::typed(
+                        ErrorType::Type,
+                        format!("{} expects a number, not {z}", native.name()),
+                    ));
+                };
+                Ok(Value::Float(if native == Native::Real { re } else { im }))
+            }
+        }
+    }
+
+    /// Returns a sorted copy of the vector, largest first if `desc` is set.
+    /// `cmp(a, b)` returns a negative number if `a` goes first, zero if they
+    /// are equal and a positive number otherwise.
+    fn sorted(
+        &mut self,
+        native: Native,
+        vec: &Value,
+        cmp: Option<Value>,
+        desc: bool,
+    ) -> Result<Value> {
+        let Value::Vec(vec) = vec else {
+            return Err(Error::typed(
+                ErrorType::Type,
+                format!("{} expects a vector, not {vec}", native.name()),
+            ));
+        };
+        if let Some(cmp) = &cmp {
+            callback(native, cmp, 2)?;
+        }
+        let items = vec.borrow().clone();
+        let items = merge_sort(items, &mut |a, b| {
+            let (a, b) = if desc { (b, a) } else { (a, b) };
+            let Some(cmp) = &cmp else {
+                return a.partial_cmp(b).ok_or_else(|| {
+                    Error::typed(ErrorType::Type, format!("Cannot compare {a} and {b}"))
+                });
+            };
+            match self.call(cmp.clone(), vec![a.clone(), b.clone()])? {
+                Value::Int(n) => Ok(n.cmp(&0)),
+                Value::Float(n) if !n.is_nan() => Ok(n.partial_cmp(&0.0).expect("not NaN")),
+                v => Err(Error::typed(
+                    ErrorType::Type,
+                    format!(
+                        "{} expects a comparator returning a number, not {v}",
+                        native.name()
+                    ),
+                )),
+            }
+        })?;
+        Ok(Value::Vec(Rc::new(RefCell::new(items))))
+    }
+
+    /// Everything a `for` loop would walk over, taken up front so callbacks
+    /// can modify the original.
+    fn collect(&mut self, val: Value) -> Result<Vec<Value>> {
+        let iter = to_iter(val)?;
+        let mut items = Vec::new();
+        while let Some(item) = self.next_value(&iter)? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    fn print_sep(&mut self, mut args: Vec<Value>) -> Result<Value> {
+        if args.is_empty() {
+            return Err(Error::typed(
+                ErrorType::Call,
+                "print_sep expects a separator as the first argument".to_string(),
+            ));
+        }
+        let sep = args.remove(0);
+        let line = args
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .join(&sep.to_string());
+        let output = self.output.as_mut().unwrap();
+        writeln!(output, "{line}").map_err(crate::error::Error::from)?;
+        Ok(args.pop().unwrap_or(Value::Nil))
+    }
+}
@@ -12,12 +12,16 @@ type Result<T> = error::Result<T, error::ParserError>;
 pub struct Parser {
     code: Rc<str>,
     tokens: std::iter::Peekable<Lexer>,
+    /// Set while parsing the key of an object literal, where a colon after an
+    /// identifier separates it from the value instead of starting a label.
+    object_key: bool,
 }
 impl Parser {
     pub fn new(tokens: Lexer) -> Parser {
         Parser {
             code: tokens.get_input(),
             tokens: tokens.into_iter().peekable(),
+            object_key: false,
         }
     }
 
@@ -31,14 +35,14 @@ impl Parser {
             }
             self.skip_whitespace();
         }
-        if result.len() == 0 {
+        if result.is_empty() {
             result.push(self.make_expr(Pos::new(0, 0), ExprType::Nil));
         }
         let pos = result
             .iter()
             .map(|e| e.pos)
             .fold(result[0].pos, |a, b| a + b);
-        Ok(self.make_expr(pos, ExprType::Block(result)))
+        Ok(self.make_expr(pos, ExprType::Program(result)))
     }
 
     fn parse_single(&mut self) -> Result<Expr> {
@@ -117,11 +121,11 @@ impl Parser {
                 );
                 continue;
             }
-            if let Some(start_loc) = self.try_consume(&TokenType::LParen) {
+            if self.try_consume(&TokenType::LParen).is_some() {
                 let args = self.parse_comma_sep_values(&TokenType::RParen)?;
                 let end_loc = self.consume(&TokenType::RParen)?;
                 left = self.make_expr(
-                    start_loc + end_loc,
+                    left.pos + end_loc,
                     ExprType::FnCall {
                         func: Box::new(left),
                         args,
@@ -157,21 +161,42 @@ impl Parser {
             }
             break;
         }
-        Ok(left)
+        // `x++` and `x--` are sugar for `x += 1` and `x -= 1`
+        let op = match self.tokens.peek().map(|t| &t.kind) {
+            Some(TokenType::PlusPlus) => Operator::Add,
+            Some(TokenType::MinusMinus) => Operator::Sub,
+            _ => return Ok(left),
+        };
+        let pos = self.tokens.next().expect("peek() was Some").pos;
+        Ok(self.make_expr(
+            left.pos + pos,
+            ExprType::AssignOp {
+                op,
+                left: Box::new(left),
+                right: Box::new(self.make_expr(pos, ExprType::Int(1))),
+            },
+        ))
     }
 
     fn parse_atom(&mut self) -> Result<Expr> {
         if let Some(Token { kind, pos }) = self.tokens.next() {
             match kind {
                 TokenType::Nil => Ok(self.make_expr(pos, ExprType::Nil)),
+                TokenType::True => Ok(self.make_expr(pos, ExprType::Bool(true))),
+                TokenType::False => Ok(self.make_expr(pos, ExprType::Bool(false))),
                 TokenType::Integer(n) => Ok(self.make_expr(pos, ExprType::Int(n))),
+                TokenType::BigInteger(n) => Ok(self.make_expr(pos, ExprType::BigInt(Rc::new(n)))),
                 TokenType::Float(n) => Ok(self.make_expr(pos, ExprType::Float(n))),
-                TokenType::Identifier(name) => Ok(self.make_expr(pos, ExprType::Identifier(name))),
+                TokenType::Identifier(name) => self.parse_identifier(name, pos),
                 TokenType::String(s) => Ok(self.make_expr(pos, ExprType::Str(Rc::new(s)))),
                 TokenType::LParen => self.parse_paren(),
                 TokenType::If => self.parse_if(pos),
-                TokenType::While => self.parse_while(pos),
-                TokenType::For => self.parse_for(pos),
+                TokenType::While => self.parse_while(pos, None),
+                TokenType::Do => self.parse_do_while(pos, None),
+                TokenType::For => self.parse_for(pos, None),
+                TokenType::Times => self.parse_times(pos, None),
+                TokenType::Let => self.parse_let(pos),
+                TokenType::Try => self.parse_try(pos),
                 TokenType::Func => self.parse_fn_def(pos),
                 TokenType::Read => self.parse_read(pos),
                 TokenType::Print => self.parse_print(pos),
@@ -180,6 +205,8 @@ impl Parser {
                 TokenType::LBracket => self.parse_vec(pos),
                 TokenType::Return => self.parse_return(pos),
                 TokenType::Use => self.parse_use(pos),
+                TokenType::Break => self.parse_loop_jump(pos, ExprType::Break),
+                TokenType::Continue => self.parse_loop_jump(pos, ExprType::Continue),
                 t => Err(Error::build(
                     format!("Unexpected token {t:?}"),
                     pos,
@@ -187,8 +214,106 @@ impl Parser {
                 )),
             }
         } else {
-            Err(format!("Unexpected EOF while parsing").into())
+            Err(String::from("Unexpected EOF while parsing").into())
+        }
+    }
+
+    fn parse_identifier(&mut self, name: String, pos: Pos) -> Result<Expr> {
+        if self.object_key || self.try_consume(&TokenType::Colon).is_none() {
+            return Ok(self.make_expr(pos, ExprType::Identifier(name)));
+        }
+        // A `label:` in front of a loop
+        let label = Some(name);
+        match self.tokens.next() {
+            Some(Token {
+                kind: TokenType::While,
+                ..
+            }) => self.parse_while(pos, label),
+            Some(Token {
+                kind: TokenType::Do,
+                ..
+            }) => self.parse_do_while(pos, label),
+            Some(Token {
+                kind: TokenType::For,
+                ..
+            }) => self.parse_for(pos, label),
+            Some(Token {
+                kind: TokenType::Times,
+                ..
+            }) => self.parse_times(pos, label),
+            Some(Token { kind, pos }) => Err(Error::build(
+                format!("Expected a loop after a label not {kind:?}"),
+                pos,
+                &self.code,
+            )),
+            None => Err(String::from("Unexpected EOF while parsing").into()),
+        }
+    }
+
+    fn parse_var_name(&mut self, keyword: &str) -> Result<String> {
+        match self.tokens.next() {
+            Some(Token {
+                kind: TokenType::Identifier(var),
+                ..
+            }) => Ok(var),
+            Some(Token { kind, pos }) => Err(Error::build(
+                format!("Expected a variable name after {keyword} not {kind:?}"),
+                pos,
+                &self.code,
+            )),
+            None => Err(String::from("Unexpected EOF while parsing").into()),
+        }
+    }
+
+    fn parse_let(&mut self, start_pos: Pos) -> Result<Expr> {
+        let var = self.parse_var_name("let")?;
+        self.consume(&TokenType::Eq)?;
+        let val = self.parse_single()?;
+        Ok(self.make_expr(
+            start_pos + val.pos,
+            ExprType::Define {
+                var,
+                val: Box::new(val),
+            },
+        ))
+    }
+
+    fn parse_try(&mut self, start_pos: Pos) -> Result<Expr> {
+        let body = self.parse_single()?;
+        self.consume(&TokenType::Catch)?;
+        let var = self.parse_var_name("catch")?;
+        let handler = self.parse_single()?;
+        Ok(self.make_expr(
+            start_pos + handler.pos,
+            ExprType::Try {
+                body: Box::new(body),
+                var,
+                handler: Box::new(handler),
+            },
+        ))
+    }
+
+    /// Parses `break` or `continue` with an optional loop label.
+    fn parse_loop_jump(
+        &mut self,
+        start_pos: Pos,
+        kind: fn(Option<String>) -> ExprType,
+    ) -> Result<Expr> {
+        if let Some(Token {
+            kind: TokenType::Identifier(_),
+            ..
+        }) = self.tokens.peek()
+        {
+            let Some(Token {
+                kind: TokenType::Identifier(label),
+                pos,
+            }) = self.tokens.next()
+            else {
+                unreachable!("peek() was an identifier");
+            };
+            return Ok(self.make_expr(start_pos + pos, kind(Some(label))));
         }
+        Ok(self.make_expr(start_pos, kind(None)))
     }
 
     fn parse_print(&mut self, start_pos: Pos) -> Result<Expr> {
@@ -204,9 +329,26 @@ impl Parser {
         Ok(self.make_expr(start_pos + end_pos, ExprType::Read))
     }
 
+    /// Parses `key: value` pairs separated by commas until the closing `}`.
     fn parse_object(&mut self, start_pos: Pos) -> Result<Expr> {
-        self.consume(&TokenType::RBrace)?;
-        Ok(self.make_expr(start_pos, ExprType::ObjectDef(Vec::new())))
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        while !self.check(&TokenType::RBrace) {
+            self.object_key = true;
+            let key = self.parse_single();
+            self.object_key = false;
+            let key = key?;
+            self.consume(&TokenType::Colon)?;
+            let value = self.parse_single()?;
+            fields.push((key, value));
+            if self.try_consume(&TokenType::Comma).is_none() {
+                break;
+            }
+            self.skip_whitespace();
+        }
+        self.skip_whitespace();
+        let end_pos = self.consume(&TokenType::RBrace)?;
+        Ok(self.make_expr(start_pos + end_pos, ExprType::ObjectDef(fields)))
     }
 
     fn parse_fn_def(&mut self, start_pos: Pos) -> Result<Expr> {
@@ -293,41 +435,114 @@ impl Parser {
         ))
     }
 
-    fn parse_while(&mut self, start_pos: Pos) -> Result<Expr> {
+    fn parse_while(&mut self, start_pos: Pos, label: Option<String>) -> Result<Expr> {
         let cond = self.parse_single()?;
         let body = self.parse_single()?;
-        let pos = start_pos + body.pos;
+        let elsebody = self.parse_loop_else()?;
+        let pos = start_pos + elsebody.as_ref().unwrap_or(&body).pos;
         Ok(self.make_expr(
             pos,
             ExprType::While {
                 cond: Box::new(cond),
                 body: Box::new(body),
+                step: None,
+                elsebody: elsebody.map(Box::new),
+                label,
             },
         ))
     }
 
-    fn parse_for(&mut self, start_pos: Pos) -> Result<Expr> {
+    fn parse_do_while(&mut self, start_pos: Pos, label: Option<String>) -> Result<Expr> {
+        let body = self.parse_single()?;
+        self.consume(&TokenType::While)?;
+        let cond = self.parse_single()?;
+        Ok(self.make_expr(
+            start_pos + cond.pos,
+            ExprType::DoWhile {
+                body: Box::new(body),
+                cond: Box::new(cond),
+                label,
+            },
+        ))
+    }
+
+    fn parse_loop_else(&mut self) -> Result<Option<Expr>> {
+        if self.try_consume(&TokenType::Else).is_some() {
+            Ok(Some(self.parse_single()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_for(&mut self, start_pos: Pos, label: Option<String>) -> Result<Expr> {
         let init = self.parse_single()?;
+        if self.try_consume(&TokenType::In).is_some() {
+            return self.parse_for_in(start_pos, init, label);
+        }
         let cond = self.parse_single()?;
         let suff = self.parse_single()?;
         let body = self.parse_single()?;
+        let elsebody = self.parse_loop_else()?;
+        let end_pos = elsebody.as_ref().unwrap_or(&body).pos;
         Ok(self.make_expr(
-            start_pos + body.pos,
+            start_pos + end_pos,
             ExprType::Block(vec![
                 init,
                 self.make_expr(
-                    cond.pos + body.pos,
+                    cond.pos + end_pos,
                     ExprType::While {
                         cond: Box::new(cond),
-                        body: Box::new(self.make_expr(body.pos, ExprType::Block(vec![body, suff]))),
+                        body: Box::new(body),
+                        step: Some(Box::new(suff)),
+                        elsebody: elsebody.map(Box::new),
+                        label,
                     },
                 ),
             ]),
         ))
     }
 
+    fn parse_for_in(&mut self, start_pos: Pos, var: Expr, label: Option<String>) -> Result<Expr> {
+        let iter = self.parse_single()?;
+        let body = self.parse_single()?;
+        let elsebody = self.parse_loop_else()?;
+        let pos = start_pos + elsebody.as_ref().unwrap_or(&body).pos;
+        Ok(self.make_expr(
+            pos,
+            ExprType::ForIn {
+                var: Box::new(var),
+                iter: Box::new(iter),
+                body: Box::new(body),
+                elsebody: elsebody.map(Box::new),
+                label,
+            },
+        ))
+    }
+
+    fn parse_times(&mut self, start_pos: Pos, label: Option<String>) -> Result<Expr> {
+        let count = self.parse_single()?;
+        let body = self.parse_single()?;
+        Ok(self.make_expr(
+            start_pos + body.pos,
+            ExprType::Times {
+                count: Box::new(count),
+                body: Box::new(body),
+                label,
+            },
+        ))
+    }
+
     fn parse_return(&mut self, start_pos: Pos) -> Result<Expr> {
-        let result = self.parse_single()?;
+        let mut result = self.parse_single()?;
+        if self.check(&TokenType::Comma) {
+            // `return a, b` is sugar for `return [a, b]`
+            let mut values = vec![result];
+            while self.try_consume(&TokenType::Comma).is_some() {
+                values.push(self.parse_single()?);
+            }
+            let pos = values[0].pos + values[values.len() - 1].pos;
+            result = self.make_expr(pos, ExprType::VecDef(values));
+        }
         Ok(self.make_expr(start_pos + result.pos, ExprType::Return(Box::new(result))))
     }
 
@@ -343,7 +558,35 @@ impl Parser {
                 &self.code,
             ));
         };
-        Ok(self.make_expr(start_pos + pos, ExprType::Use(filename)))
+        if self.try_consume(&TokenType::LBrace).is_none() {
+            return Ok(self.make_expr(
+                start_pos + pos,
+                ExprType::Use {
+                    filename,
+                    names: None,
+                },
+            ));
+        }
+        let names = self
+            .parse_comma_sep_values(&TokenType::RBrace)?
+            .into_iter()
+            .map(|e| match e.kind {
+                ExprType::Identifier(name) => Ok(name),
+                kind => Err(Error::build(
+                    format!("Can only import plain identifiers not {kind:?}"),
+                    e.pos,
+                    &self.code,
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let end_pos = self.consume(&TokenType::RBrace)?;
+        Ok(self.make_expr(
+            start_pos + end_pos,
+            ExprType::Use {
+                filename,
+                names: Some(names),
+            },
+        ))
     }
 
     fn skip_whitespace(&mut self) {
@@ -364,12 +607,8 @@ impl Parser {
     }
 
     fn try_consume_operator(&mut self, ops: Option<&HashSet<Operator>>) -> Option<(Pos, Operator)> {
-        let Some(Token { pos: _, kind }) = self.tokens.peek() else {
-            return None;
-        };
-        let Some(op) = kind.to_operator() else {
-            return None;
-        };
+        let Token { pos: _, kind } = self.tokens.peek()?;
+        let op = kind.to_operator()?;
         if let Some(ops) = ops {
             if !ops.contains(&op) {
                 return None;
@@ -381,15 +620,19 @@ impl Parser {
     }
 
     fn try_consume_assign_operator(&mut self) -> Option<(Pos, Operator)> {
-        let Some(Token { pos: _, kind }) = self.tokens.peek() else {
-            return None;
-        };
+        let Token { pos: _, kind } = self.tokens.peek()?;
         let op = match kind {
             TokenType::PlusEq => Operator::Add,
             TokenType::MinusEq => Operator::Sub,
             TokenType::StarEq => Operator::Mul,
             TokenType::SlashEq => Operator::Div,
+            TokenType::SlashSlashEq => Operator::FloorDiv,
             TokenType::PercentEq => Operator::Mod,
+            TokenType::AndEq => Operator::BitAnd,
+            TokenType::PipeEq => Operator::BitOr,
+            TokenType::CaretEq => Operator::XOr,
+            TokenType::LessLessEq => Operator::LeftShift,
+            TokenType::GreaterGreaterEq => Operator::RightShift,
             TokenType::Eq => Operator::Eq,
             _ => return None,
         };
@@ -2,53 +2,263 @@ use std::cell::RefCell;
 use std::fmt::Display;
 use std::hash::Hash;
 use std::rc::Rc;
-use std::{collections::HashMap, ops::AddAssign};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::{AddAssign, Deref, DerefMut},
+};
 
+use crate::bigint::BigInt;
+use crate::bytecode::Operation;
 use crate::error::{self, Stackable};
-use crate::{bytecode::Operation, token::Pos};
+use crate::native::Native;
+use crate::token::{Pos, Snippet};
 
 #[derive(Debug, Clone)]
 pub enum Value {
+    Bool(bool),
     Int(i64),
     Float(f64),
+    /// A complex number as its real and imaginary part.
+    Complex(f64, f64),
+    /// A reduced fraction with a denominator larger than one, see [`Value::rational`].
+    Rational(i64, i64),
+    /// An integer that doesn't fit into an `Int`, see [`Value::big`].
+    BigInt(Rc<BigInt>),
     Str(Rc<String>),
+    /// Raw bytes, which unlike a string don't have to be valid UTF-8.
+    Bytes(Rc<Vec<u8>>),
     Vec(Rc<RefCell<Vec<Value>>>),
     Fn {
         num_params: usize,
         captured: Vec<Value>,
         chunk: Rc<Chunk>,
     },
+    Native(Native),
     Nil,
     Ref(Rc<RefCell<Value>>),
-    Obj(Rc<RefCell<HashMap<Value, Value>>>),
+    Obj(Rc<RefCell<Object>>),
+    Iter(Rc<RefCell<Iter>>),
+}
+
+/// A lazy sequence of values walked by `for x in ...` loops.
+#[derive(Debug)]
+pub enum Iter {
+    Range {
+        next: i64,
+        end: i64,
+        step: i64,
+    },
+    Vec {
+        vec: Rc<RefCell<Vec<Value>>>,
+        idx: usize,
+    },
+    Str {
+        s: Rc<String>,
+        idx: usize,
+    },
+    Bytes {
+        b: Rc<Vec<u8>>,
+        idx: usize,
+    },
+    /// Lines read from stdin.
+    Lines,
+    /// An object with a `next` function that returns `nil` once it's done.
+    Object(Value),
 }
 
 impl Value {
     pub fn truthy(&self) -> bool {
         match self {
+            Self::Bool(b) => *b,
             Self::Int(v) => v != &0,
             Self::Float(v) => v != &0.0,
-            Self::Str(s) => s.len() != 0,
+            Self::Complex(re, im) => re != &0.0 || im != &0.0,
+            Self::Rational(n, _) => n != &0,
+            Self::BigInt(_) => true,
+            Self::Str(s) => !s.is_empty(),
+            Self::Bytes(b) => !b.is_empty(),
             Self::Nil => false,
-            Self::Vec(v) => v.borrow().len() != 0,
+            Self::Vec(v) => !v.borrow().is_empty(),
             Self::Fn {
                 num_params: _,
                 captured: _,
                 chunk: _,
             } => true,
+            Self::Native(_) => true,
             Self::Ref(v) => v.borrow().truthy(),
-            Self::Obj(v) => v.borrow().len() != 0,
+            Self::Obj(v) => !v.borrow().is_empty(),
+            Self::Iter(_) => true,
+        }
+    }
+
+    /// The name `type(x)` returns. Big integers are just an `int` to the
+    /// program, and natives are functions like any other.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Bool(_) => "bool",
+            Self::Int(_) | Self::BigInt(_) => "int",
+            Self::Float(_) => "float",
+            Self::Complex(..) => "complex",
+            Self::Rational(..) => "frac",
+            Self::Str(_) => "str",
+            Self::Bytes(_) => "bytes",
+            Self::Vec(_) => "vec",
+            Self::Fn { .. } | Self::Native(_) => "fn",
+            Self::Nil => "nil",
+            Self::Ref(_) => "ref",
+            Self::Obj(_) => "obj",
+            Self::Iter(_) => "iter",
+        }
+    }
+
+    /// Real and imaginary part of any number.
+    pub fn as_complex(&self) -> Option<(f64, f64)> {
+        match self {
+            Self::Complex(re, im) => Some((*re, *im)),
+            v => Some((v.as_float()?, 0.0)),
+        }
+    }
+
+    /// Any real number as a float.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Self::Int(i) => Some(*i as f64),
+            Self::Float(f) => Some(*f),
+            Self::Rational(n, d) => Some(*n as f64 / *d as f64),
+            Self::BigInt(n) => Some(n.to_f64()),
+            _ => None,
+        }
+    }
+
+    /// Numerator and denominator of an integer or a rational.
+    pub fn as_fraction(&self) -> Option<(i128, i128)> {
+        match self {
+            Self::Int(i) => Some((*i as i128, 1)),
+            Self::Rational(n, d) => Some((*n as i128, *d as i128)),
+            _ => None,
+        }
+    }
+
+    /// Any integer as a big integer.
+    pub fn as_big(&self) -> Option<BigInt> {
+        match self {
+            Self::Int(i) => Some(BigInt::from(*i)),
+            Self::BigInt(n) => Some((**n).clone()),
+            _ => None,
+        }
+    }
+
+    /// Turns the number back into an `Int` if it fits, so that equal integers
+    /// always have the same representation.
+    pub fn big(n: BigInt) -> Value {
+        match n.to_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::BigInt(Rc::new(n)),
+        }
+    }
+
+    /// Reduces the fraction, which becomes an `Int` if the denominator ends up
+    /// being one.
+    pub fn rational(num: i128, den: i128) -> Result<Value, String> {
+        if den == 0 {
+            return Err(format!("Fraction {num}/{den} has a zero denominator"));
+        }
+        let (mut a, mut b) = (num.abs(), den.abs());
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        let sign = den.signum();
+        let (num, den) = (sign * num / a, sign * den / a);
+        match (i64::try_from(num), i64::try_from(den)) {
+            (Ok(num), Ok(1)) => Ok(Value::Int(num)),
+            (Ok(num), Ok(den)) => Ok(Value::Rational(num, den)),
+            _ => Err(format!("Fraction {num}/{den} doesn't fit into 64 bits")),
+        }
+    }
+
+    /// Copies vectors and objects all the way down instead of sharing them.
+    pub fn deep_clone(&self) -> Value {
+        match self {
+            Self::Vec(v) => Self::Vec(Rc::new(RefCell::new(
+                v.borrow().iter().map(Value::deep_clone).collect(),
+            ))),
+            Self::Obj(o) => {
+                let o = o.borrow();
+                Self::Obj(Rc::new(RefCell::new(Object {
+                    fields: o.iter().map(|(k, v)| (k.clone(), v.deep_clone())).collect(),
+                    default: o.default.as_ref().map(Value::deep_clone),
+                })))
+            }
+            v => v.clone(),
+        }
+    }
+}
+
+/// The fields of an object. Objects made by `defaultdict` also have a default
+/// that isn't one of the fields.
+#[derive(Debug, Default)]
+pub struct Object {
+    pub fields: HashMap<Value, Value>,
+    pub default: Option<Value>,
+}
+
+impl Deref for Object {
+    type Target = HashMap<Value, Value>;
+    fn deref(&self) -> &Self::Target {
+        &self.fields
+    }
+}
+
+impl DerefMut for Object {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.fields
+    }
+}
+
+impl From<HashMap<Value, Value>> for Object {
+    fn from(fields: HashMap<Value, Value>) -> Self {
+        Self {
+            fields,
+            default: None,
+        }
+    }
+}
+
+/// Looks up a key of an object. Missing keys are `nil`, unless the object has
+/// a default, in which case a fresh copy of it is stored under the key.
+pub fn obj_get(obj: &mut Object, key: &Value) -> Value {
+    if let Some(val) = obj.get(key) {
+        return val.clone();
+    }
+    match &obj.default {
+        Some(default) => {
+            let val = default.deep_clone();
+            obj.insert(key.clone(), val.clone());
+            val
         }
+        None => Value::Nil,
+    }
+}
+
+impl From<Iter> for Value {
+    fn from(iter: Iter) -> Self {
+        Value::Iter(Rc::new(RefCell::new(iter)))
     }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => a == b,
             (Self::Int(a), Self::Int(b)) => a == b,
             (Self::Float(a), Self::Float(b)) => a == b,
+            (Self::Complex(a, b), Self::Complex(c, d)) => a == c && b == d,
+            (Self::Rational(a, b), Self::Rational(c, d)) => a == c && b == d,
+            (Self::BigInt(a), Self::BigInt(b)) => a == b,
             (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::Bytes(a), Self::Bytes(b)) => a == b,
             (Self::Nil, Self::Nil) => true,
+            (Self::Native(a), Self::Native(b)) => a == b,
             (Self::Vec(a), Self::Vec(b)) => {
                 a.borrow().len() == b.borrow().len()
                     && a.borrow()
@@ -64,6 +274,7 @@ impl PartialEq for Value {
                         .all(|(a, b)| a == b)
             }
             (Self::Ref(v), other) | (other, Self::Ref(v)) => other.eq(&v.borrow()),
+            (Self::Iter(a), Self::Iter(b)) => Rc::ptr_eq(a, b),
             (
                 Self::Fn {
                     num_params,
@@ -86,11 +297,25 @@ impl Eq for Value {}
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => a.partial_cmp(b),
             (Self::Int(a), Self::Int(b)) => a.partial_cmp(b),
             (Self::Int(a), Self::Float(b)) => (*a as f64).partial_cmp(b),
             (Self::Float(a), Self::Int(b)) => a.partial_cmp(&(*b as f64)),
             (Self::Float(a), Self::Float(b)) => a.partial_cmp(b),
+            (Self::Rational(..), Self::Int(_) | Self::Rational(..))
+            | (Self::Int(_), Self::Rational(..)) => {
+                let ((a, b), (c, d)) = (self.as_fraction()?, other.as_fraction()?);
+                (a * d).partial_cmp(&(c * b))
+            }
+            (Self::BigInt(_), Self::Int(_) | Self::BigInt(_)) | (Self::Int(_), Self::BigInt(_)) => {
+                self.as_big()?.partial_cmp(&other.as_big()?)
+            }
+            (Self::Rational(..) | Self::BigInt(_), Self::Float(_))
+            | (Self::Float(_), Self::Rational(..) | Self::BigInt(_)) => {
+                self.as_float()?.partial_cmp(&other.as_float()?)
+            }
             (Self::Str(a), Self::Str(b)) => a.partial_cmp(b),
+            (Self::Bytes(a), Self::Bytes(b)) => a.partial_cmp(b),
             (Self::Nil, Self::Nil) => Some(std::cmp::Ordering::Equal),
             (Self::Vec(a), Self::Vec(b)) => {
                 for (x, y) in a.borrow().iter().zip(b.borrow().iter()) {
@@ -110,9 +335,14 @@ impl PartialOrd for Value {
 impl Hash for Value {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         match self {
+            Self::Bool(b) => b.hash(state),
             Self::Int(i) => i.hash(state),
             Self::Float(n) => n.to_bits().hash(state),
+            Self::Complex(re, im) => (re.to_bits(), im.to_bits()).hash(state),
+            Self::Rational(n, d) => (n, d).hash(state),
+            Self::BigInt(n) => n.hash(state),
             Self::Str(s) => s.hash(state),
+            Self::Bytes(b) => b.hash(state),
             Self::Vec(v) => v.borrow().hash(state),
             Self::Nil => 0.hash(state),
             Self::Ref(v) => v.borrow().hash(state),
@@ -123,16 +353,34 @@ impl Hash for Value {
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Value::Bool(b) => write!(f, "{b}"),
             Value::Int(i) => write!(f, "{i}"),
             Value::Float(n) => write!(f, "{n}"),
+            Value::Complex(re, im) if im.is_sign_negative() => write!(f, "{re}-{}i", -im),
+            Value::Complex(re, im) => write!(f, "{re}+{im}i"),
+            Value::Rational(n, d) => write!(f, "{n}/{d}"),
+            Value::BigInt(n) => write!(f, "{n}"),
             Value::Str(s) => write!(f, "{s}"),
+            // Printable ASCII is shown as is, everything else escaped.
+            Value::Bytes(b) => {
+                write!(f, "b\"")?;
+                for &c in b.iter() {
+                    match c {
+                        b'"' => write!(f, "\\\"")?,
+                        b'\\' => write!(f, "\\\\")?,
+                        b' '..=b'~' => write!(f, "{}", c as char)?,
+                        c => write!(f, "\\x{c:02x}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
             Value::Vec(v) => {
                 write!(f, "[")?;
                 for (i, a) in v.borrow().iter().enumerate() {
                     if i != 0 {
                         write!(f, ", ")?;
                     }
-                    write!(f, "{a}")?;
+                    write!(f, "{}", Repr(a))?;
                 }
                 write!(f, "]")?;
                 Ok(())
@@ -154,11 +402,16 @@ impl Display for Value {
                         }
                     })
                     .collect();
-                if captured_var_names.len() > 0 {
+                if !captured_var_names.is_empty() {
                     crate::interpreter::fmt_vec(f, &captured_var_names)?;
                 }
                 write!(f, "{{ {} bytes }}>", chunk.num_bytecode())
             }
+            Value::Native(native) => write!(f, "<native {}>", native.name()),
+            Value::Iter(iter) => match &*iter.borrow() {
+                Iter::Range { next, end, step } => write!(f, "<range {next}..{end} by {step}>"),
+                _ => write!(f, "<iterator>"),
+            },
             Value::Ref(v) => write!(f, "*{}", v.borrow()),
             Value::Obj(o) => {
                 write!(f, "{{=")?;
@@ -166,7 +419,7 @@ impl Display for Value {
                     if i != 0 {
                         write!(f, ", ")?;
                     }
-                    write!(f, "{k}: {v}")?;
+                    write!(f, "{}: {}", Repr(k), Repr(v))?;
                 }
                 write!(f, "}}")?;
                 Ok(())
@@ -175,6 +428,34 @@ impl Display for Value {
     }
 }
 
+/// Displays a value the way it's written in code, so strings are quoted and
+/// escaped. `Display` only does that for elements of vectors and objects.
+pub struct Repr<'a>(pub &'a Value);
+
+impl Display for Repr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Value::Str(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::Ref(v) => write!(f, "*{}", Repr(&v.borrow())),
+            // Vectors and objects already show their elements with `Repr`.
+            v => write!(f, "{v}"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Capture {
     Local,
@@ -182,21 +463,65 @@ pub enum Capture {
     Captured(usize),
 }
 
+/// How a call sets up the variables of a function, worked out once when the
+/// function is compiled instead of on every call.
+#[derive(Debug, Clone, Default)]
+pub struct InitPlan {
+    /// Variables captured by inner functions, which have to live behind a `Ref`.
+    pub owned: Vec<usize>,
+    /// Variables captured from the parent as `(index, index in parent)` pairs.
+    pub captured: Vec<(usize, usize)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Chunk {
     pub code: Rc<str>,
+    pub source_name: Option<Rc<str>>,
     pub bytecode: Vec<Operation>,
     pub pos: Vec<Pos>,
     pub constants: Vec<Value>,
     var_index: HashMap<String, usize>,
     pub var_names: Vec<String>,
     pub captured_vars: Vec<Capture>,
+    pub init: InitPlan,
+    /// String constants shared by this chunk, its children and imports.
+    pub strings: Rc<RefCell<HashSet<Rc<String>>>>,
+    /// Assigning to a variable that wasn't declared is an error, and so are
+    /// warnings. `print` returns `nil` instead of the printed value.
+    pub strict: bool,
+    /// Warnings of this chunk, its children and imports.
+    pub warnings: Rc<RefCell<Vec<error::Error<error::Warning>>>>,
+    /// Variables visible before each block that is being compiled.
+    scopes: Vec<HashMap<String, usize>>,
     parent: Option<Box<Chunk>>,
+    stack_depth: isize,
+    loops: Vec<LoopContext>,
+    /// Number of `try` bodies that are being compiled.
+    tries: usize,
+}
+
+#[derive(Debug, Clone)]
+struct LoopContext {
+    label: Option<String>,
+    depth: isize,
+    tries: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
 }
 
 impl Chunk {
+    pub fn named(code: Rc<str>, source_name: Option<Rc<str>>) -> Self {
+        let mut chunk: Chunk = code.into();
+        chunk.source_name = source_name;
+        chunk
+    }
+
     pub fn to_child(self) -> Self {
         let mut child: Chunk = self.code.clone().into();
+        child.source_name = self.source_name.clone();
+        child.strings = self.strings.clone();
+        child.strict = self.strict;
+        child.warnings = self.warnings.clone();
         child.parent = Some(Box::new(self));
         child
     }
@@ -206,16 +531,40 @@ impl Chunk {
     }
 
     pub fn push_op(&mut self, op: Operation, pos: Pos) -> usize {
+        self.stack_depth += op.stack_effect();
         self.bytecode.push(op);
         self.pos.push(pos);
         self.bytecode.len() - 1
     }
 
     pub fn push_const(&mut self, val: Value) -> usize {
+        if let Value::Str(s) = val {
+            let s = self.intern(s);
+            let existing = self.constants.iter().position(|c| match c {
+                Value::Str(c) => Rc::ptr_eq(c, &s),
+                _ => false,
+            });
+            if let Some(idx) = existing {
+                return idx;
+            }
+            self.constants.push(Value::Str(s));
+            return self.constants.len() - 1;
+        }
         self.constants.push(val);
         self.constants.len() - 1
     }
 
+    /// Returns the shared copy of the string, so equal string constants
+    /// only live in memory once.
+    pub fn intern(&self, s: Rc<String>) -> Rc<String> {
+        let mut strings = self.strings.borrow_mut();
+        if let Some(existing) = strings.get(&s) {
+            return existing.clone();
+        }
+        strings.insert(s.clone());
+        s
+    }
+
     pub fn get_const(&self, idx: usize) -> &Value {
         &self.constants[idx]
     }
@@ -225,7 +574,7 @@ impl Chunk {
     }
 
     pub fn num_var(&self) -> usize {
-        self.var_index.len()
+        self.var_names.len()
     }
 
     pub fn num_bytecode(&self) -> usize {
@@ -262,6 +611,19 @@ impl Chunk {
         idx
     }
 
+    /// Builds the [`InitPlan`] once all variables of the chunk are known.
+    pub fn finish(&mut self) {
+        let mut init = InitPlan::default();
+        for (i, c) in self.captured_vars.iter().enumerate() {
+            match c {
+                Capture::Local => {}
+                Capture::Owned => init.owned.push(i),
+                Capture::Captured(idx) => init.captured.push((i, *idx)),
+            }
+        }
+        self.init = init;
+    }
+
     pub fn def_var(&mut self, name: &str) -> usize {
         if let Some(v) = self.var_index.get(name) {
             return *v;
@@ -273,6 +635,181 @@ impl Chunk {
         idx
     }
 
+    pub fn begin_scope(&mut self) {
+        self.scopes.push(self.var_index.clone());
+    }
+
+    /// Forgets the variables declared since the matching `begin_scope`. Their
+    /// slots are kept, since compiled code and closures still refer to them.
+    pub fn end_scope(&mut self) {
+        self.var_index = self.scopes.pop().expect("end_scope without begin_scope");
+    }
+
+    /// Whether `name` was declared in the innermost scope that is being
+    /// compiled, rather than in an outer block or function.
+    pub fn declared_in_scope(&self, name: &str) -> bool {
+        let Some(&idx) = self.var_index.get(name) else {
+            return false;
+        };
+        if matches!(self.captured_vars[idx], Capture::Captured(_)) {
+            return false;
+        }
+        match self.scopes.last() {
+            Some(outer) => outer.get(name) != Some(&idx),
+            None => true,
+        }
+    }
+
+    /// Declares a new variable even if one with the same name already exists.
+    /// Code compiled from now on uses the new one, while code compiled before,
+    /// including functions that captured it, keeps using the old one.
+    pub fn shadow_var(&mut self, name: &str) -> usize {
+        let idx = self.num_var();
+        self.var_index.insert(name.to_string(), idx);
+        self.captured_vars.push(Capture::Local);
+        self.var_names.push(name.to_string());
+        idx
+    }
+
+    /// Lists every instruction with its offset, operand and the source
+    /// position it was compiled from. Pass `color` to highlight it for a
+    /// terminal.
+    pub fn disassemble(&self, color: bool) -> String {
+        let (bold, dim, reset) = if color {
+            ("\x1b[1m", "\x1b[90m", "\x1b[0m")
+        } else {
+            ("", "", "")
+        };
+        let width = self.bytecode.len().saturating_sub(1).to_string().len();
+        let mut result = String::new();
+        for (i, (op, pos)) in self.bytecode.iter().zip(self.pos.iter()).enumerate() {
+            let operand = op.operand().map(|n| n.to_string()).unwrap_or_default();
+            let Snippet { line, col, .. } = pos.extract(&self.code);
+            result += &format!(
+                "{dim}{i:>width$}{reset}  {bold}{:<12}{reset}{operand:>3}  {dim}{line}:{col}{reset}\n",
+                op.mnemonic(),
+            );
+        }
+        result
+    }
+
+    /// Number of values the compiled code leaves on the stack on top of the
+    /// variables. Code paths that are merged by jumps need to reset it.
+    pub fn stack_depth(&self) -> isize {
+        self.stack_depth
+    }
+
+    pub fn set_stack_depth(&mut self, depth: isize) {
+        self.stack_depth = depth;
+    }
+
+    pub fn begin_loop(&mut self, label: Option<String>) {
+        self.loops.push(LoopContext {
+            label,
+            depth: self.stack_depth,
+            tries: self.tries,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+    }
+
+    /// Points all breaks of the innermost loop to the next instruction.
+    pub fn end_loop(&mut self) -> error::Result<(), error::SyntaxError> {
+        let ctx = self.loops.pop().expect("end_loop without begin_loop");
+        for idx in ctx.breaks {
+            self.jump_from(idx)?;
+        }
+        Ok(())
+    }
+
+    /// Points all continues of the innermost loop to the next instruction.
+    pub fn continue_here(&mut self) -> error::Result<(), error::SyntaxError> {
+        let ctx = self
+            .loops
+            .last_mut()
+            .expect("continue_here outside of a loop");
+        for idx in std::mem::take(&mut ctx.continues) {
+            self.jump_from(idx)?;
+        }
+        Ok(())
+    }
+
+    pub fn begin_try(&mut self) {
+        self.tries += 1;
+    }
+
+    pub fn end_try(&mut self) {
+        self.tries -= 1;
+    }
+
+    /// Jumps out of the innermost loop, or the one with the given label, with
+    /// a nil result, dropping any values the loop body left on the stack.
+    pub fn break_loop(
+        &mut self,
+        label: Option<&str>,
+        pos: Pos,
+    ) -> error::Result<(), error::SyntaxError> {
+        let (loop_idx, idx) = self.leave_body("break", label, pos)?;
+        self.loops[loop_idx].breaks.push(idx);
+        Ok(())
+    }
+
+    /// Skips the rest of the body of the innermost loop, or the one with the
+    /// given label, as if the body evaluated to nil.
+    pub fn continue_loop(
+        &mut self,
+        label: Option<&str>,
+        pos: Pos,
+    ) -> error::Result<(), error::SyntaxError> {
+        let (loop_idx, idx) = self.leave_body("continue", label, pos)?;
+        self.loops[loop_idx].continues.push(idx);
+        Ok(())
+    }
+
+    /// Cleans up the stack down to the loop body and pushes an unpatched jump
+    /// with a nil on top of it.
+    fn leave_body(
+        &mut self,
+        keyword: &str,
+        label: Option<&str>,
+        pos: Pos,
+    ) -> error::Result<(usize, usize), error::SyntaxError> {
+        let depth = self.stack_depth;
+        let loop_idx = self.find_loop(keyword, label, pos)?;
+        for _ in self.loops[loop_idx].depth..depth {
+            self.push_op(Operation::Pop, pos);
+        }
+        for _ in self.loops[loop_idx].tries..self.tries {
+            self.push_op(Operation::TryEnd, pos);
+        }
+        self.push_op(Operation::Nil, pos);
+        let idx = self.push_op(Operation::Jump(0), pos);
+        self.stack_depth = depth + 1;
+        Ok((loop_idx, idx))
+    }
+
+    fn find_loop(
+        &self,
+        keyword: &str,
+        label: Option<&str>,
+        pos: Pos,
+    ) -> error::Result<usize, error::SyntaxError> {
+        let found = match label {
+            None => self.loops.len().checked_sub(1),
+            Some(label) => self
+                .loops
+                .iter()
+                .rposition(|l| l.label.as_deref() == Some(label)),
+        };
+        found.ok_or_else(|| {
+            let msg = match label {
+                None => format!("Cannot {keyword} outside of a loop"),
+                Some(label) => format!("Unknown loop label {label}"),
+            };
+            error::Error::build(msg, pos, &self.code)
+        })
+    }
+
     pub fn jump_from(&mut self, from: usize) -> error::Result<(), error::SyntaxError> {
         let idx = self.bytecode.len();
         if from >= idx {
@@ -284,7 +821,10 @@ impl Chunk {
         }
 
         match &mut self.bytecode[from] {
-            Operation::Jump(v) | Operation::JumpIf(v) => {
+            Operation::Jump(v)
+            | Operation::JumpIf(v)
+            | Operation::IterNext(v)
+            | Operation::TryBegin(v) => {
                 let tmp = idx - from - 1;
                 *v = tmp.try_into().map_err(|e| {
                     error::Error::from(e).wrap(
@@ -308,13 +848,22 @@ impl From<Rc<str>> for Chunk {
     fn from(code: Rc<str>) -> Self {
         Self {
             code,
+            source_name: None,
             bytecode: vec![],
             pos: vec![],
             constants: vec![],
             var_index: HashMap::new(),
             var_names: vec![],
             captured_vars: vec![],
+            init: InitPlan::default(),
+            strings: Rc::default(),
+            strict: false,
+            warnings: Rc::default(),
+            scopes: vec![],
             parent: None,
+            stack_depth: 0,
+            loops: vec![],
+            tries: 0,
         }
     }
 }
@@ -340,9 +889,76 @@ impl Display for Chunk {
             .enumerate()
             .for_each(|(i, (s, c))| writeln!(f, "{i}: {s:?} ({c:?})").unwrap());
         writeln!(f, "=== Bytecode ===").unwrap();
-        self.bytecode
+        write!(f, "{}", self.disassemble(f.alternate()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::Value;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    #[test]
+    fn disassemble() {
+        let code: Rc<str> = Rc::from("a = 1\nprint(a + 20)");
+        let expr = Parser::new(Lexer::new(code.clone())).parse().unwrap();
+        let chunk = expr.to_chunk(code.into()).unwrap();
+        assert_eq!(
+            chunk.disassemble(false),
+            [
+                "0  Constant      0  1:5",
+                "1  SetVar        0  1:1",
+                "2  Pop              1:1",
+                "3  GetVar        0  2:7",
+                "4  Constant      1  2:11",
+                "5  Add              2:7",
+                "6  Print         1  2:1",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn interned_strings() {
+        let code: Rc<str> = Rc::from("a = \"hi\"\nb = \"hi\"\nf = fn() a.hi");
+        let expr = Parser::new(Lexer::new(code.clone())).parse().unwrap();
+        let chunk = expr.to_chunk(code.into()).unwrap();
+        let strings: Vec<_> = chunk
+            .constants
             .iter()
-            .for_each(|op| writeln!(f, "{:?}", op).unwrap());
-        Ok(())
+            .filter_map(|c| match c {
+                Value::Str(s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(strings.len(), 1);
+        let Some(Value::Fn { chunk: f, .. }) = chunk.constants.last() else {
+            panic!("expected a function constant");
+        };
+        let Value::Str(key) = &f.constants[0] else {
+            panic!("expected a string constant");
+        };
+        assert!(Rc::ptr_eq(&strings[0], key));
+    }
+
+    #[test]
+    fn child_chunk_source() {
+        let code: Rc<str> = Rc::from("x = 1\nf = fn(a) {\n    a * x\n}");
+        let expr = Parser::new(Lexer::new(code.clone())).parse().unwrap();
+        let chunk = expr.to_chunk(code.clone().into()).unwrap();
+        let Some(Value::Fn { chunk: f, .. }) = chunk.constants.last() else {
+            panic!("expected a function constant");
+        };
+        assert!(Rc::ptr_eq(&f.code, &code));
+        let snippets: Vec<_> = f.pos.iter().map(|p| p.extract(&f.code)).collect();
+        assert!(
+            snippets
+                .iter()
+                .any(|s| (s.line, s.col, s.snippet.as_str()) == (3, 5, "a * x")),
+            "{snippets:?}"
+        );
     }
 }
@@ -0,0 +1,454 @@
+//! A compact binary format for compiled chunks, so programs don't have to be
+//! compiled again every time they run.
+//!
+//! The format starts with [`MAGIC`] and [`VERSION`], followed by the top level
+//! chunk. Functions are stored as constants with their chunk nested inside.
+//! Lengths, indices and positions are stored as LEB128 varints.
+use std::rc::Rc;
+
+use crate::{
+    bigint::BigInt,
+    bytecode::Operation,
+    error::BytecodeError,
+    native::Native,
+    runtime::{Capture, Chunk, Value},
+    token::Pos,
+};
+
+type Error = crate::error::Error<BytecodeError>;
+type Result<T> = crate::error::Result<T, BytecodeError>;
+
+pub const MAGIC: &[u8; 4] = b"AOCB";
+/// Bump whenever the format or the meaning of an operation changes, older
+/// files are then rejected.
+pub const VERSION: u8 = 4;
+
+impl Chunk {
+    /// Serializes a fully compiled chunk with all of its functions.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut writer = Writer {
+            bytes: MAGIC.to_vec(),
+        };
+        writer.bytes.push(VERSION);
+        writer.chunk(self, None)?;
+        Ok(writer.bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Chunk> {
+        let mut reader = Reader { bytes, idx: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::new("Not an AOCLang bytecode file".to_string()));
+        }
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(Error::new(format!(
+                "Unsupported bytecode version {version}, expected {VERSION}"
+            )));
+        }
+        let chunk = reader.chunk(None)?;
+        if reader.idx != bytes.len() {
+            return Err(Error::new("Unexpected data after the bytecode".to_string()));
+        }
+        Ok(chunk)
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn varint(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.bytes.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.bytes.push(n as u8);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.varint(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    /// Functions share the code of their parent, so it's only stored when it
+    /// differs, which only happens for imported files.
+    fn chunk(&mut self, chunk: &Chunk, parent: Option<&Chunk>) -> Result<()> {
+        match parent {
+            Some(p) if p.code == chunk.code && p.source_name == chunk.source_name => {
+                self.bytes.push(0)
+            }
+            _ => {
+                self.bytes.push(1);
+                self.str(&chunk.code);
+                match &chunk.source_name {
+                    Some(name) => {
+                        self.bytes.push(1);
+                        self.str(name);
+                    }
+                    None => self.bytes.push(0),
+                }
+            }
+        }
+        self.varint(chunk.bytecode.len());
+        for (op, pos) in chunk.bytecode.iter().zip(chunk.pos.iter()) {
+            self.bytes.extend_from_slice(&op.encode());
+            self.varint(pos.start);
+            self.varint(pos.end);
+        }
+        self.varint(chunk.var_names.len());
+        for (name, capture) in chunk.var_names.iter().zip(chunk.captured_vars.iter()) {
+            self.str(name);
+            match capture {
+                Capture::Local => self.bytes.push(0),
+                Capture::Owned => self.bytes.push(1),
+                Capture::Captured(idx) => {
+                    self.bytes.push(2);
+                    self.varint(*idx);
+                }
+            }
+        }
+        self.varint(chunk.constants.len());
+        for val in chunk.constants.iter() {
+            self.value(val, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, val: &Value, chunk: &Chunk) -> Result<()> {
+        match val {
+            Value::Nil => self.bytes.push(0),
+            Value::Int(n) => {
+                self.bytes.push(1);
+                self.bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::Float(n) => {
+                self.bytes.push(2);
+                self.bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::Str(s) => {
+                self.bytes.push(3);
+                self.str(s);
+            }
+            Value::Fn {
+                num_params,
+                captured,
+                chunk: f,
+            } if captured.is_empty() => {
+                self.bytes.push(4);
+                self.varint(*num_params);
+                self.chunk(f, Some(chunk))?;
+            }
+            Value::Native(native) => {
+                self.bytes.push(5);
+                self.str(native.name());
+            }
+            Value::Bool(b) => self.bytes.extend_from_slice(&[6, *b as u8]),
+            Value::BigInt(n) => {
+                self.bytes.push(7);
+                self.str(&n.to_string());
+            }
+            v => return Err(Error::new(format!("Cannot serialize constant {v}"))),
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    idx: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        let end = self
+            .idx
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err(Error::new("Unexpected end of bytecode".to_string()));
+        };
+        let bytes = &self.bytes[self.idx..end];
+        self.idx = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<usize> {
+        let mut n = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as usize) << shift;
+            if byte < 0x80 {
+                return Ok(n);
+            }
+        }
+        Err(Error::new("Number in bytecode is too large".to_string()))
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.varint()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(Error::from)
+    }
+
+    fn eight(&mut self) -> Result<[u8; 8]> {
+        Ok(self.take(8)?.try_into().expect("took exactly 8 bytes"))
+    }
+
+    fn chunk(&mut self, parent: Option<&Chunk>) -> Result<Chunk> {
+        let mut chunk = match (self.byte()?, parent) {
+            (0, Some(p)) => Chunk::named(p.code.clone(), p.source_name.clone()),
+            (1, _) => {
+                let code = Rc::from(self.str()?);
+                let source_name = match self.byte()? {
+                    0 => None,
+                    _ => Some(Rc::from(self.str()?)),
+                };
+                Chunk::named(code, source_name)
+            }
+            _ => return Err(Error::new("Missing source code in bytecode".to_string())),
+        };
+        if let Some(p) = parent {
+            chunk.strings = p.strings.clone();
+        }
+        for _ in 0..self.varint()? {
+            let [tag, operand] = [self.byte()?, self.byte()?];
+            let op = Operation::decode(tag, operand)
+                .ok_or_else(|| Error::new(format!("Unknown operation {tag}")))?;
+            let pos = Pos {
+                start: self.varint()?,
+                end: self.varint()?,
+            };
+            let code = &chunk.code;
+            if pos.start > pos.end
+                || !code.is_char_boundary(pos.start)
+                || !code.is_char_boundary(pos.end)
+            {
+                return Err(Error::new(format!("Invalid position {pos:?}")));
+            }
+            chunk.push_op(op, pos);
+        }
+        let mut captured_vars = Vec::new();
+        for _ in 0..self.varint()? {
+            chunk.shadow_var(&self.str()?);
+            captured_vars.push(match self.byte()? {
+                0 => Capture::Local,
+                1 => Capture::Owned,
+                2 => match self.varint()? {
+                    idx if parent.is_some_and(|p| idx < p.num_var()) => Capture::Captured(idx),
+                    idx => return Err(Error::new(format!("Invalid captured variable {idx}"))),
+                },
+                c => return Err(Error::new(format!("Unknown variable kind {c}"))),
+            });
+        }
+        chunk.captured_vars = captured_vars;
+        for _ in 0..self.varint()? {
+            let val = match self.value(&chunk)? {
+                Value::Str(s) => Value::Str(chunk.intern(s)),
+                val => val,
+            };
+            chunk.constants.push(val);
+        }
+        validate(&chunk)?;
+        chunk.finish();
+        Ok(chunk)
+    }
+
+    fn value(&mut self, chunk: &Chunk) -> Result<Value> {
+        Ok(match self.byte()? {
+            0 => Value::Nil,
+            1 => Value::Int(i64::from_le_bytes(self.eight()?)),
+            2 => Value::Float(f64::from_le_bytes(self.eight()?)),
+            3 => Value::Str(Rc::new(self.str()?)),
+            4 => Value::Fn {
+                num_params: self.varint()?,
+                captured: Vec::new(),
+                chunk: Rc::new(self.chunk(Some(chunk))?),
+            },
+            5 => {
+                let name = self.str()?;
+                Value::Native(
+                    Native::from_name(&name)
+                        .ok_or_else(|| Error::new(format!("Unknown native {name}")))?,
+                )
+            }
+            6 => Value::Bool(self.byte()? != 0),
+            7 => {
+                let digits = self.str()?;
+                let n = BigInt::parse(&digits)
+                    .ok_or_else(|| Error::new(format!("Invalid integer {digits}")))?;
+                Value::BigInt(Rc::new(n))
+            }
+            t => return Err(Error::new(format!("Unknown constant type {t}"))),
+        })
+    }
+}
+
+/// Checks that operands point at existing constants, variables and
+/// instructions, and that every operation finds the values it needs on the
+/// stack. Bytecode that passes can still fail at runtime like any program,
+/// but it can't make the interpreter read past its stack or bytecode.
+fn validate(chunk: &Chunk) -> Result<()> {
+    let len = chunk.bytecode.len();
+    for (i, op) in chunk.bytecode.iter().enumerate() {
+        let valid = match *op {
+            Operation::Constant(n) => (n as usize) < chunk.constants.len(),
+            Operation::GetVar(n) | Operation::SetVar(n) => (n as usize) < chunk.num_var(),
+            Operation::Jump(n)
+            | Operation::JumpIf(n)
+            | Operation::IterNext(n)
+            | Operation::TryBegin(n) => i + 1 + n as usize <= len,
+            Operation::JumpBack(n) => n as usize <= i + 1,
+            _ => true,
+        };
+        if !valid {
+            return Err(Error::new(format!("Invalid operation {op:?} at {i}")));
+        }
+    }
+    check_stack_depth(chunk)
+}
+
+/// Walks every path through the bytecode, checking that each instruction is
+/// always reached with the same number of values on the stack above the
+/// variables, and that there are enough of them for the instruction to run.
+fn check_stack_depth(chunk: &Chunk) -> Result<()> {
+    let mut depths = vec![None; chunk.bytecode.len() + 1];
+    let mut todo = vec![(0, 0)];
+    while let Some((i, depth)) = todo.pop() {
+        match depths[i] {
+            Some(d) if d == depth => continue,
+            Some(_) => return Err(Error::new(format!("Inconsistent stack depth at {i}"))),
+            None => depths[i] = Some(depth),
+        }
+        // Running past the last instruction returns the top of the stack.
+        let Some(&op) = chunk.bytecode.get(i) else {
+            if depth == 0 {
+                return Err(Error::new("Missing return value".to_string()));
+            }
+            continue;
+        };
+        if depth < op.stack_args() {
+            return Err(Error::new(format!("Ran out of stack for {op:?} at {i}")));
+        }
+        let next = depth
+            .checked_add_signed(op.stack_effect())
+            .expect("checked the arguments");
+        match op {
+            Operation::Return => {}
+            Operation::Jump(n) => todo.push((i + 1 + n as usize, next)),
+            Operation::JumpBack(n) => todo.push((i + 1 - n as usize, next)),
+            Operation::JumpIf(n) => todo.extend([(i + 1, next), (i + 1 + n as usize, next)]),
+            // An exhausted iterator jumps without pushing a value, and a
+            // caught error is pushed onto the stack as it was at `TryBegin`.
+            Operation::IterNext(n) => todo.extend([(i + 1, next), (i + 1 + n as usize, depth)]),
+            Operation::TryBegin(n) => todo.extend([(i + 1, next), (i + 1 + n as usize, depth + 1)]),
+            _ => todo.push((i + 1, next)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::{
+        aoc::{compile_and_run, compile_to_bytes, run_bytes},
+        bytecode::Operation,
+        error::ErrorType,
+        interpreter::Interpreter,
+        runtime::{Chunk, Value},
+        token::Pos,
+    };
+
+    fn run_restored(bytes: &[u8]) -> String {
+        let mut output = Vec::new();
+        run_bytes(bytes, &mut output);
+        String::from_utf8_lossy(&output).to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let code: Rc<str> = Rc::from(
+            "counter = fn() { n = 0\n fn() n += 1 }
+c = counter()
+c()
+obj = {=}
+obj.name = \"aoc\"
+print(c(), 1.5 * 2, obj, [1, nil] * 2, print_sep, true, !true, 99999999999999999999)
+[][3]",
+        );
+        let mut want = Vec::new();
+        compile_and_run(code.clone(), &mut want);
+        let bytes = compile_to_bytes(None, code.clone(), false, Vec::new()).unwrap();
+        let restored = Chunk::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.to_bytes().unwrap(), bytes);
+        assert_eq!(run_restored(&bytes), String::from_utf8_lossy(&want));
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        let bytes = compile_to_bytes(None, Rc::from("print(1)"), false, Vec::new()).unwrap();
+        let err = |bytes: &[u8]| Chunk::from_bytes(bytes).err().unwrap().to_string();
+        assert!(err(b"print(1)").contains("Not an AOCLang bytecode file"));
+        let mut newer = bytes.clone();
+        newer[4] += 1;
+        assert!(err(&newer).contains("Unsupported bytecode version 5, expected 4"));
+        assert!(err(&bytes[..bytes.len() - 1]).contains("Unexpected end of bytecode"));
+        assert!(run_restored(&newer).starts_with("=== Stderr ===\nBytecodeError: "));
+    }
+
+    #[test]
+    fn corrupted_operations() {
+        let bytes = compile_to_bytes(None, Rc::from("1 + 2"), false, Vec::new()).unwrap();
+        let chunk = Chunk::from_bytes(&bytes).unwrap();
+        let with_ops = |ops: &[Operation]| {
+            let mut chunk = chunk.clone();
+            chunk.bytecode = ops.to_vec();
+            chunk.pos = vec![Pos::new(0, 1); ops.len()];
+            let bytes = chunk.to_bytes().unwrap();
+            assert!(run_restored(&bytes).starts_with("=== Stderr ===\nBytecodeError: "));
+            Chunk::from_bytes(&bytes).err().unwrap().to_string()
+        };
+        use Operation::*;
+        assert!(with_ops(&[Constant(0), Add]).contains("Ran out of stack for Add at 1"));
+        assert!(with_ops(&[Constant(0), Clone(1)]).contains("Ran out of stack for Clone(1) at 1"));
+        assert!(with_ops(&[Constant(0), Swap(3)]).contains("Ran out of stack for Swap(3) at 1"));
+        assert!(with_ops(&[Constant(0), Pop, Return]).contains("Ran out of stack for Return"));
+        assert!(with_ops(&[Constant(0), Pop]).contains("Missing return value"));
+        assert!(
+            with_ops(&[Constant(0), IterNext(9)]).contains("Invalid operation IterNext(9) at 1")
+        );
+        assert!(
+            with_ops(&[TryBegin(5), Constant(0)]).contains("Invalid operation TryBegin(5) at 0")
+        );
+        // The jump skips a value that the other path pushes.
+        let ops = [Constant(0), JumpIf(1), Constant(0), Constant(1)];
+        assert!(with_ops(&ops).contains("Inconsistent stack depth at 3"));
+    }
+
+    #[test]
+    fn missing_captured_values() {
+        let code = Rc::from("n = 0\nfn() n");
+        let bytes = compile_to_bytes(None, code, false, Vec::new()).unwrap();
+        let chunk = Rc::new(Chunk::from_bytes(&bytes).unwrap());
+        // The constant is called as is, without the values it captures.
+        let func = chunk
+            .constants
+            .iter()
+            .find(|c| matches!(c, Value::Fn { .. }))
+            .unwrap()
+            .clone();
+        let mut output = Vec::new();
+        let err = Interpreter::new(chunk, &mut output)
+            .call(func, vec![])
+            .unwrap_err();
+        assert_eq!(err.error_type(), ErrorType::Call);
+        assert!(err
+            .to_string()
+            .contains("function captures 1 variables, but got 0 values"));
+    }
+}
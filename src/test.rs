@@ -1,6 +1,15 @@
 use std::{collections::HashMap, fs, rc::Rc};
 
-use crate::aoc::compile_and_run;
+use crate::{
+    aoc::{check_named, compile_and_run, debug_run, parse, run_named, RunOptions},
+    bytecode::Operation,
+    expr::{Expr, ExprType, Operator},
+    interpreter::Interpreter,
+    lexer::Lexer,
+    parser::Parser,
+    runtime::{Chunk, Value},
+    token::Pos,
+};
 
 macro_rules! interpret_tests {
     ($($name:ident,)*) => {
@@ -20,6 +29,47 @@ fn test_examples() {
     }
 }
 
+/// Runs the README examples that show code after `> `, continued by indented
+/// lines, and its output after `| `. Blocks with anything else, like `-> `
+/// values or errors, are only illustrations.
+#[test]
+fn readme_examples() {
+    let readme = fs::read_to_string("README.md").expect("README.md doesn't exist.");
+    let mut checked = 0;
+    for block in readme.split("```").skip(1).step_by(2) {
+        let (mut code, mut want) = (String::new(), Vec::new());
+        let (mut in_code, mut checkable) = (false, true);
+        for line in block.lines().filter(|l| !l.is_empty()) {
+            if let Some(c) = line.strip_prefix("> ") {
+                code += c;
+                in_code = true;
+            } else if let Some(c) = line.strip_prefix("  ").filter(|_| in_code) {
+                code += c;
+            } else if let Some(out) = line.strip_prefix("| ").or((line == "|").then_some("")) {
+                want.push(out);
+                in_code = false;
+                continue;
+            } else {
+                checkable &= line.starts_with("-> ");
+                in_code = false;
+                continue;
+            }
+            code.push('\n');
+        }
+        if !checkable || code.is_empty() || want.is_empty() {
+            continue;
+        }
+        let out = run(&code);
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            want,
+            "README example:\n{code}"
+        );
+        checked += 1;
+    }
+    assert!(checked > 0);
+}
+
 fn run_single_example(test_case: &str) {
     run_and_compare(
         &format!("./examples/{}.aoc", test_case),
@@ -43,6 +93,25 @@ fn run_and_compare(code_file: &str, out_file: &str) {
     );
 }
 
+fn strict_options() -> RunOptions<'static> {
+    RunOptions {
+        strict: true,
+        ..RunOptions::default()
+    }
+}
+
+fn run(code: &str) -> String {
+    let mut output = Vec::new();
+    compile_and_run(Rc::from(code), &mut output);
+    String::from_utf8_lossy(&output).to_string()
+}
+
+fn run_strict(code: &str) -> String {
+    let mut output = Vec::new();
+    run_named(None, Rc::from(code), strict_options(), &mut output);
+    String::from_utf8_lossy(&output).to_string()
+}
+
 fn collect_examples() -> Vec<(String, String)> {
     let mut result: HashMap<String, (String, String)> = HashMap::new();
     for file in fs::read_dir("./examples").expect("Example folder doesn't exist.") {
@@ -61,4 +130,1778 @@ interpret_tests! {
     primes,
     sort,
     dfs,
+    std,
+}
+
+#[test]
+fn vec_literal_evaluation_order() {
+    let code = "v = [print(\"a\"), print(\"b\"), print(\"c\")]
+print(v)
+i = 0
+next = fn() i += 1
+print([next(), [next(), next()], next()])";
+    assert_eq!(
+        run(code),
+        "a\nb\nc\n[\"a\", \"b\", \"c\"]\n[1, [2, 3], 4]\n"
+    );
+}
+
+#[test]
+fn argument_evaluation_order() {
+    let code = "f = fn(a, b) a + b
+print(f(print(\"a\"), print(\"b\")))
+i = 0
+next = fn() i += 1
+g = fn(a, b, c) [a, b, c]
+print(g(next(), next(), next()))
+print_sep(\",\", next(), next())";
+    assert_eq!(run(code), "a\nb\nab\n[1, 2, 3]\n4,5\n");
+}
+
+#[test]
+fn call_stack_in_errors() {
+    let output = run("inner = fn(x) x + nil\nouter = fn(x) inner(x)\nouter(1)\n");
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines[1], "RuntimeError: Unsupported Add for 1 and nil");
+    assert!(lines[2].starts_with("on line 1:15:") && lines[2].contains("x + nil"));
+    assert!(lines[3].starts_with("on line 2:15:") && lines[3].contains("inner(x)"));
+    assert!(lines[4].starts_with("on line 3:1:") && lines[4].contains("outer(1)"));
+}
+
+#[test]
+fn runtime_error_snippet() {
+    let code: Rc<str> = Rc::from("a = 1\nb = \"x\" - a\n");
+    let expr = Parser::new(Lexer::new(code.clone())).parse().unwrap();
+    let chunk = expr.to_chunk(code.into()).unwrap();
+    let err = Interpreter::new(Rc::new(chunk), Vec::new())
+        .run()
+        .unwrap_err()
+        .to_string();
+    let lines: Vec<_> = err.lines().collect();
+    assert!(lines[0].contains("Unsupported Sub for x and 1"), "{err}");
+    assert!(lines[1].starts_with("on line 2:5:") && lines[1].contains("\"x\" - a"));
+    assert!(lines[1].contains("b = "), "{err}");
+}
+
+#[test]
+fn imported_source_name_in_errors() {
+    let lib = std::env::temp_dir().join("aoc_lang_source_name_lib.aoc");
+    fs::write(&lib, "bad = fn(x) x + nil\n").unwrap();
+    let lib = lib.to_str().unwrap();
+    let output = run(&format!("lib = use \"{lib}\"\nlib.bad(1)\n"));
+    let lines: Vec<_> = output.lines().collect();
+    assert!(lines[2].starts_with(&format!("in {lib} on line 1:13:")));
+    assert!(lines[3].starts_with("on line 2:4:"));
+}
+
+#[test]
+fn selective_import() {
+    let lib = std::env::temp_dir().join("aoc_lang_selective_import_lib.aoc");
+    fs::write(
+        &lib,
+        "factor = 2\ndouble = fn(x) x * factor\n_hi = \"hi \"\ngreet = fn(name) _hi + name\n",
+    )
+    .unwrap();
+    let lib = lib.to_str().unwrap();
+    let code = format!(
+        "factor = 10
+ns = use \"{lib}\" {{ double, greet }}
+print(double(21), \" \", greet(\"aoc\"), \" \", factor, \" \", ns.factor)"
+    );
+    assert_eq!(run(&code), "42 hi aoc 10 2\n");
+    assert!(run(&format!("use \"{lib}\" {{ triple }}"))
+        .contains(&format!("Imported file {lib} doesn't export triple")));
+    assert!(run(&format!("use \"{lib}\" {{ _hi }}"))
+        .contains(&format!("Imported file {lib} doesn't export _hi")));
+}
+
+#[test]
+fn import_namespace() {
+    let lib = std::env::temp_dir().join("aoc_lang_import_namespace_lib.aoc");
+    fs::write(
+        &lib,
+        "_calls = 0\nhelper = fn(x) { _calls += 1\n x + _calls }\nsum = helper(1)\n",
+    )
+    .unwrap();
+    let lib = lib.to_str().unwrap();
+    let code = format!(
+        "lib = use \"{lib}\"
+print(lib.helper(10), \" \", lib.sum, \" \", lib._calls)"
+    );
+    assert_eq!(run(&code), "12 2 nil\n");
+}
+
+#[test]
+fn strict_mode() {
+    let code = "inc = fn(x) { x = x + 1 }\ncount = 1";
+    assert_eq!(run(code), "");
+    let lines: Vec<_> = run_strict(code).lines().map(String::from).collect();
+    assert_eq!(
+        lines[1],
+        "SyntaxError: Assignment to undeclared variable inc, declare it with let"
+    );
+    assert!(lines[2].starts_with("on line 1:1:"), "{lines:?}");
+    let out = run_strict("print((fn(x) { x = x * 2 })(21))");
+    assert_eq!(out, "42\n");
+    for out in [run("x = 1\nx = y"), run_strict("print(y)")] {
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[1], "SyntaxError: Unknown variable y");
+    }
+    assert!(run("x = 1\nx = y").contains("on line 2:5:"));
+    assert!(run_strict("[a, b] = [1, 2]").contains("Assignment to undeclared variable a"));
+}
+
+#[test]
+fn let_declarations() {
+    let code = "let count = 1
+count += 1
+count = count * 10
+let fact = fn(n) if n < 2 1 else n * fact(n - 1)
+let [a, b] = [1, 2]
+print(count, \" \", fact(5))";
+    assert!(run_strict(code).contains("Expected a variable name after let"));
+    let code = code.replace("let [a, b] = [1, 2]\n", "");
+    assert_eq!(run_strict(&code), "20 120\n");
+    assert_eq!(run(&code), "20 120\n");
+    let lines: Vec<_> = run_strict("let total = 0\ntotl = 5")
+        .lines()
+        .map(String::from)
+        .collect();
+    assert_eq!(
+        lines[1],
+        "SyntaxError: Assignment to undeclared variable totl, declare it with let"
+    );
+    assert!(lines[2].starts_with("on line 2:1:"), "{lines:?}");
+}
+
+#[test]
+fn let_shadowing() {
+    let code = "x = 1
+f = fn() {
+    y = x
+    let x = 5
+    x += 1
+    [y, x]
+}
+print(f(), \" \", x)
+get = fn() x
+let x = 10
+print(get(), \" \", x)
+let x = x
+print(x)";
+    let want = "[1, 6] 1\n1 10\n10\n";
+    let out = run(code);
+    assert!(out.ends_with(&format!("let x = x\x1b[0m\n{want}")), "{out}");
+    let bytes = crate::aoc::compile_to_bytes(None, Rc::from(code), false, Vec::new()).unwrap();
+    let mut output = Vec::new();
+    crate::aoc::run_bytes(&bytes, &mut output);
+    assert_eq!(String::from_utf8_lossy(&output), want);
+}
+
+#[test]
+fn block_scope() {
+    let code = "a = 1
+get = {
+    a = a + 1
+    b = a * 10
+    fn() b
+}
+print(a, \" \", get())";
+    assert_eq!(run(code), "2 20\n");
+    let out = run(&format!("{code}\nif a {{ b = 5 }}\nprint(b)"));
+    assert!(out.contains("Unknown variable b\non line 9:7"), "{out}");
+    assert!(run("for i = 0; i < 3; i += 1 {}\nprint(i)").contains("Unknown variable i"));
+    assert!(run("while 0 { last = 1 }\nprint(last)").contains("Unknown variable last"));
+}
+
+#[test]
+fn try_catch() {
+    let code = "x = try 1 / 0 catch e {
+    print(e.message)
+    -1
+}
+print(x, \" \", try 5 catch e 0)
+f = fn(n) [1, 2][n]
+print(try f(5) catch err \"bad: \" + err.message)
+for i = 0; i < 5; i += 1 {
+    try { if i == 3 break; f(i) } catch e print(i)
+}
+1 / 0";
+    assert_eq!(
+        run(code),
+        "Unsupported Div for 1 and 0\n-1 5\nbad: Index 5 out of range for vector of length 2\n2\n\
+=== Stderr ===\nRuntimeError: Unsupported Div for 1 and 0\n\
+on line 11:1: \x1b[91m\x1b[1m1 / 0\x1b[0m\n"
+    );
+}
+
+#[test]
+fn parse_api() {
+    let expr = parse("x = 1 + 2").unwrap();
+    let ExprType::Program(exprs) = expr.kind else {
+        panic!("expected a program, not {:?}", expr.kind);
+    };
+    let [Expr {
+        kind: ExprType::Assign { left, right },
+        ..
+    }] = exprs.as_slice()
+    else {
+        panic!("expected an assignment, not {exprs:?}");
+    };
+    assert!(matches!(&left.kind, ExprType::Identifier(name) if name == "x"));
+    assert!(matches!(
+        right.kind,
+        ExprType::BinaryOp {
+            op: Operator::Add,
+            ..
+        }
+    ));
+    assert!(parse("x = (1").is_err());
+}
+
+#[test]
+fn object_literals() {
+    let code = "x = \"k\"
+o = {= \"a\": 1, x: 2 + 3, 7: [1, 2], \"a\": 4, }
+print_sep(\" \", o.a, o.k, o[7], {=}, {= }, {=
+  \"n\": {= \"m\": 1 },
+})
+loop: while 1 { break loop }";
+    assert_eq!(run(code), "4 5 [1, 2] {=} {=} {=\"n\": {=\"m\": 1}}\n");
+    assert!(run("{= \"a\" 1}").contains("ParserError"));
+}
+
+#[test]
+fn nested_index_assignment() {
+    let code = "grid = new_grid(2, 3, 0)
+grid[1][2] = 5
+grid[0][-1] += 2
+row = grid[1]
+row[0] = 7
+print(grid, \" \", grid[1][2])
+obj = {=}
+obj.a = [[1]]
+obj.a[0][0] = 9
+obj.b = {=}
+obj.b.c = [1, 2]
+obj.b.c[1] = 3
+print(obj.a, \" \", obj.b.c)";
+    assert_eq!(run(code), "[[0, 0, 2], [7, 0, 5]] 5\n[[9]] [1, 3]\n");
+}
+
+#[test]
+fn increment_decrement() {
+    let code = "i = 0
+i++
+i++
+v = [5, 6]
+v[1]--
+print_sep(\" \", i, v[1], i++, i--, v[0]++, v)
+for j = 0; j < 3; j++ print(j)
+print(2 - -1)";
+    assert_eq!(run(code), "2 5 3 2 6 [6, 5]\n0\n1\n2\n3\n");
+    assert!(run("1++").contains("Can only assign to plain variables and vectors"));
+}
+
+#[test]
+fn swap_operation() {
+    // `v[i] op= x` swaps the new value below the vector and index.
+    assert_eq!(run("v = [1, 2, 3]\nv[0] += 10\nprint(v)"), "[11, 2, 3]\n");
+
+    for (depth, want) in [(0, "[10, 20, 30, 40]"), (2, "[10, 40, 30, 20]")] {
+        let mut chunk = Chunk::named(Rc::from(""), None);
+        let pos = Pos::new(0, 0);
+        for val in [10, 20, 30, 40] {
+            let idx = chunk.push_const(Value::Int(val));
+            chunk.push_op(Operation::Constant(idx as u8), pos);
+        }
+        chunk.push_op(Operation::Swap(depth), pos);
+        chunk.push_op(Operation::VecCollect(4), pos);
+        let mut output = Vec::new();
+        let result = Interpreter::new(Rc::new(chunk), &mut output).run();
+        assert_eq!(result.unwrap().to_string(), want, "Swap({depth})");
+    }
+}
+
+#[test]
+fn clone_depth() {
+    // `Clone(n)` copies the value `n` slots below the top of the stack and
+    // leaves everything else in place. `VecCollect` keeps the order in which
+    // the values were pushed.
+    for (depth, want) in [(0, 40), (1, 30), (2, 20), (3, 10)] {
+        let mut chunk = Chunk::named(Rc::from(""), None);
+        let pos = Pos::new(0, 0);
+        for val in [10, 20, 30, 40] {
+            let idx = chunk.push_const(Value::Int(val));
+            chunk.push_op(Operation::Constant(idx as u8), pos);
+        }
+        chunk.push_op(Operation::Clone(depth), pos);
+        chunk.push_op(Operation::VecCollect(5), pos);
+        let mut output = Vec::new();
+        let result = Interpreter::new(Rc::new(chunk), &mut output).run();
+        assert_eq!(
+            result.unwrap().to_string(),
+            format!("[10, 20, 30, 40, {want}]"),
+            "Clone({depth})"
+        );
+    }
+}
+
+#[test]
+fn strict_print_returns_nil() {
+    let code = "let done = fn() { print(\"done\") }
+let x = print(1, 2)
+print(done(), \" \", x)";
+    assert_eq!(run(code), "12\ndone\ndone 2\n");
+    assert_eq!(run_strict(code), "12\ndone\nnil nil\n");
+}
+
+#[test]
+fn check_without_running() {
+    let check = |code: &str| {
+        let mut output = Vec::new();
+        let ok = check_named(
+            Some(Rc::from("check.aoc")),
+            Rc::from(code),
+            false,
+            &mut output,
+        );
+        (ok, String::from_utf8_lossy(&output).to_string())
+    };
+    assert_eq!(check("print(1)\nx = [1][5]"), (true, String::new()));
+
+    let constants: Vec<_> = (0..300).map(|i| i.to_string()).collect();
+    let (ok, out) = check(&format!("print(1)\nv = [{}]", constants.join(", ")));
+    assert!(!ok);
+    assert!(
+        out.starts_with("=== Stderr ===\nSyntaxError: More than 255 constants in local scope"),
+        "{out}"
+    );
+    assert!(out.contains("\non line 2:"), "{out}");
+
+    let (ok, out) = check("print(1)\nx = (1");
+    assert!(!ok);
+    assert!(out.starts_with("=== Stderr ===\nParserError: "), "{out}");
+}
+
+#[test]
+fn shadow_warnings() {
+    let nested = "let i = 1
+let f = fn(i) { let i = i * 2 }
+{
+    let i = 10
+    for let j = 0; j < i; j += 5 { let i = j }
+}
+print(f(i), i)";
+    assert_eq!(run(nested), "21\n");
+    assert_eq!(run_strict(nested), "21\n");
+    let same = "let i = 1\n{\n    let j = 2\n    let j = 3\n}\nprint(i)";
+    assert_eq!(
+        run(same),
+        "=== Warning ===\nWarning: let j shadows a variable in the same scope
+on line 4:5:     \x1b[91m\x1b[1mlet j = 3\x1b[0m\n1\n"
+    );
+    let lines: Vec<_> = run_strict(same).lines().map(String::from).collect();
+    assert_eq!(
+        lines[1],
+        "SyntaxError: let j shadows a variable in the same scope"
+    );
+    assert!(run("i = 0\nlet i = 1").contains("Warning: let i shadows"));
+}
+
+#[test]
+fn return_multiple_values() {
+    let output = run("solve = fn(x) { return x + 1, x * 2 }\n[p1, p2] = solve(10)\nprint(p1, \" \", p2)\nprint(solve(1))\n");
+    assert_eq!(output, "11 20\n[2, 2]\n");
+}
+
+#[test]
+fn unpack_size_mismatch() {
+    let output = run("x = 0\n[a, b] = [1]\n");
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(
+        lines[1],
+        "RuntimeError: Not enough values to unpack: expected 2, got 1"
+    );
+    assert!(lines[2].starts_with("on line 2:1:") && lines[2].contains("[a, b] = [1]"));
+
+    let output = run("[a, b] = [1, 2, 3]\n");
+    assert!(output.contains("RuntimeError: Too many values to unpack: expected 2, got 3"));
+
+    let output = run("[a, b] = 12\n");
+    assert!(output.contains("RuntimeError: Can only unpack a vector into 2 variables, not 12"));
+}
+
+#[test]
+fn print_sep() {
+    assert_eq!(run("print_sep(\" \", 1, 2, 3)"), "1 2 3\n");
+    assert_eq!(
+        run("x = print_sep(\", \", \"a\", [1])\nprint(x)"),
+        "a, [1]\n[1]\n"
+    );
+    assert_eq!(run("print(1, 2, 3)"), "123\n");
+    assert_eq!(run("print_sep = 5\nprint(print_sep)"), "5\n");
+}
+
+#[test]
+fn counting_loop() {
+    assert_eq!(
+        run("i = 0\nsum = 0\nwhile i < 1000 {\n    i += 1\n    sum += i\n}\nprint(i, \" \", sum)"),
+        "1000 500500\n"
+    );
+    assert_eq!(run("i = 5\nwhile i < 3 i += 1\nprint(i)"), "5\n");
+}
+
+#[test]
+fn continue_loops() {
+    assert_eq!(
+        run("i = 0\nwhile i < 6 {\n    i += 1\n    if i % 2 continue\n    print(i)\n}"),
+        "2\n4\n6\n"
+    );
+    // The step of a for loop still runs
+    assert_eq!(
+        run("for i = 0; i < 5; i += 1 { if i == 2 continue\nprint(i) }"),
+        "0\n1\n3\n4\n"
+    );
+    assert_eq!(
+        run("for x in [1, 2, 3] { if x == 2 continue\nprint(x) }"),
+        "1\n3\n"
+    );
+    assert_eq!(
+        run("i = 0\ndo { i += 1\nif i < 3 continue\nprint(i) } while i < 4"),
+        "3\n4\n"
+    );
+    assert_eq!(
+        run("n = 0\ntimes 4 { n += 1\ncontinue\nn = 100 }\nprint(n)"),
+        "4\n"
+    );
+    // A skipped body evaluates to nil
+    assert_eq!(
+        run("print(for x in [1, 2] if x == 2 continue else x)"),
+        "nil\n"
+    );
+    assert_eq!(
+        run("i = 0\nwhile i < 3 { i += 1\ntry { if i == 2 continue\nprint(i) } catch e 0 }"),
+        "1\n3\n"
+    );
+    assert!(run("continue").contains("Cannot continue outside of a loop"));
+    assert!(run("while 1 { f = fn() continue }").contains("Cannot continue outside of a loop"));
+}
+
+#[test]
+fn continue_nested_loops() {
+    let code = "for y in [1, 2] {\n    for x in [1, 2, 3] {\n        if x == 2 continue\n        print(y, x)\n    }\n}";
+    assert_eq!(run(code), "11\n13\n21\n23\n");
+    let code = "outer: for y in [1, 2] {\n    for x in [1, 2, 3] {\n        if x == 2 continue outer\n        print(y, x)\n    }\n    print(\"unreachable\")\n}";
+    assert_eq!(run(code), "11\n21\n");
+    assert!(run("a: while 1 { continue b }").contains("Unknown loop label b"));
+}
+
+#[test]
+fn loop_else() {
+    let find = "find = fn(v, x) {\n    i = 0\n    while i < +v {\n        if v[i] == x break\n        i += 1\n    } else print(\"not found\")\n    i\n}\n";
+    assert_eq!(run(&format!("{find}print(find([1, 2, 3], 2))")), "1\n");
+    assert_eq!(
+        run(&format!("{find}print(find([1, 2, 3], 5))")),
+        "not found\n3\n"
+    );
+    assert_eq!(
+        run("i = nil\nfor i = 0; i < 3; i += 1 { if i == 1 break } else print(\"done\")\nprint(i)"),
+        "1\n"
+    );
+    assert_eq!(
+        run("i = nil\nfor i = 0; i < 3; i += 1 { if i == 5 break } else print(\"done\")\nprint(i)"),
+        "done\n3\n"
+    );
+}
+
+#[test]
+fn break_drops_temporaries() {
+    assert_eq!(
+        run("i = nil\nfor i = 0; i < 5; i += 1 print(i, 10 + if i > 1 break else 0)\nprint(i)"),
+        "010\n110\n2\n"
+    );
+    assert_eq!(run("print(while 1 break)"), "nil\n");
+    assert!(run("while 1 { f = fn() break }").contains("Cannot break outside of a loop"));
+}
+
+#[test]
+fn do_while() {
+    assert_eq!(
+        run("x = 10\ny = do {\n    print(x)\n    x += 1\n} while x < 4\nprint(y)"),
+        "10\n11\n"
+    );
+    assert_eq!(run("x = 0\ndo x += 1 while x < 3\nprint(x)"), "3\n");
+    assert_eq!(
+        run("x = 0\nprint(do { x += 1\nif x == 2 break } while 1)\nprint(x)"),
+        "nil\n2\n"
+    );
+}
+
+#[test]
+fn labeled_break() {
+    let code = "found = y = nil
+outer: for y = 0; y < 10; y += 1 {
+    for x = 0; x < 10; x += 1 {
+        if x * y == 12 {
+            found = [x, y]
+            break outer
+        }
+    }
+}
+print(found, y)";
+    assert_eq!(run(code), "[6, 2]2\n");
+    let code =
+        "outer: while 1 { inner: while 1 { break inner }\nprint(\"after inner\")\nbreak outer }";
+    assert_eq!(run(code), "after inner\n");
+    assert!(run("a: while 1 { break b }").contains("Unknown loop label b"));
+    assert!(run("a: print(1)").contains("Expected a loop after a label"));
+    assert!(run("a = 1 : 2").contains("ParserError: Unexpected token Colon\non line 1:7"));
+}
+
+#[test]
+fn assignment_semantics() {
+    assert_eq!(
+        run("a = b = [1]\na << 2\nprint(b)\nprint(c = 3, c)"),
+        "[1, 2]\n33\n"
+    );
+    assert_eq!(
+        run("s = i = 0\nfor i = 0; i < 4; i += 1 s = s + i\nprint(s, i)"),
+        "64\n"
+    );
+    assert_eq!(run("x = if 1 y = 5 else 0\nprint(x, y)"), "55\n");
+}
+
+#[test]
+fn call_initializes_variables() {
+    // Arguments captured by closures, captured parent variables and plain
+    // locals all have to be set up correctly on every call.
+    let code = "base = 100
+counter = fn(start) {
+    inc = fn() start += 1
+    local = nil
+    fn() { local = inc() + base }
+}
+c = counter(1)
+d = counter(10)
+print(c(), c(), d())
+depth = fn(n) if n == 0 0 else 1 + depth(n - 1)
+print(depth(50))";
+    assert_eq!(run(code), "102103111\n50\n");
+}
+
+#[test]
+fn recursive_calls() {
+    let code = "fib = fn(n) if n < 2 n else fib(n - 1) + fib(n - 2)
+print(fib(20))
+pair = fn(a, b) [a, b]
+print(pair(fib(5), pair(1, fib(6))))
+v = []
+fail = fn(n) if n == 0 v[1] else fail(n - 1)
+fail(3)";
+    let out = run(code);
+    assert!(out.starts_with("6765\n[5, [1, 8]]\n"), "{out}");
+    assert!(out.contains("Index 1 out of range"), "{out}");
+}
+
+#[test]
+fn trailing_comments() {
+    let code = "x = 1 # one
+s = \"a # b\" # not a comment inside the string
+f = fn(a) { # start
+    a + 1 # add
+} # end
+{
+    y = f(x)# tight
+    print(s, \" \", y) # print
+}
+if x # check
+    print(x)";
+    assert_eq!(run(code), "a # b 2\n1\n");
+}
+
+#[test]
+fn function_bodies() {
+    let code = "empty = fn() {}
+ignore = fn(a, b) {}
+inc = fn(x) x + 1
+print(empty(), ignore(1, 2), inc(2), [empty(), inc(inc(0))])";
+    assert_eq!(run(code), "nilnil3[nil, 2]\n");
+}
+
+#[test]
+fn call_chaining() {
+    let code = "f = fn() 5
+g = f
+print(f)
+print(g == f, g())
+make = fn() fn() 7
+print(make()())
+add = fn(a) fn(b) a + b
+print(add(1)(2))";
+    let out = run(code);
+    assert!(out.starts_with("<fn(){ "), "{out}");
+    assert!(out.ends_with("}>\ntrue5\n7\n3\n"), "{out}");
+}
+
+#[test]
+fn captured_variables() {
+    let code = "x = y = nil
+f = fn() x + y
+x = 1
+y = 2
+print(f())
+x = 10
+print(f())";
+    assert_eq!(run(code), "3\n12\n");
+
+    let expr = Parser::new(Lexer::new(Rc::from(code))).parse().unwrap();
+    let chunk = Rc::new(
+        expr.to_chunk(Chunk::named(expr.code.clone(), None))
+            .unwrap(),
+    );
+    let Some(Value::Fn {
+        num_params,
+        chunk: f,
+        ..
+    }) = chunk
+        .constants
+        .iter()
+        .find(|c| matches!(c, Value::Fn { .. }))
+    else {
+        panic!("missing function constant");
+    };
+    let malformed = Value::Fn {
+        num_params: *num_params,
+        captured: vec![Value::Int(1)],
+        chunk: f.clone(),
+    };
+    let err = Interpreter::new(chunk.clone(), Vec::new())
+        .call(malformed, Vec::new())
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("function captures 2 variables, but got 1 values"));
+}
+
+#[test]
+fn grid_natives() {
+    let code = "g = {=}
+h = {=}
+for y = -2; y < 3; y += 1
+    for x = -2; x < 3; x += 1 {
+        grid_set(g, x, y, x * 10 + y)
+        h[[x, y]] = x * 10 + y
+    }
+same = 1
+for y = -2; y < 3; y += 1
+    for x = -2; x < 3; x += 1
+        same = same && grid_get(g, x, y) == h[[x, y]]
+print(same, grid_get(g, 5, 5), grid_set(g, 0, 0, \"a\"), grid_get(g, 0, 0))
+grid_get(g, 1)";
+    let out = run(code);
+    assert!(out.starts_with("truenilaa\n"), "{out}");
+    assert!(
+        out.contains("grid_get expects 3 arguments, but got 2"),
+        "{out}"
+    );
+    assert!(run("grid_get({=}, 1, 5000000000)").contains("don't fit into 32 bits"));
+}
+
+#[test]
+fn debug_dump_positions() {
+    let mut output = Vec::new();
+    debug_run(Rc::from("a = 1\nprint(a + 2)"), &mut output);
+    let out = String::from_utf8_lossy(&output);
+    assert!(
+        out.contains("=== Next operation ===\n0: Constant(0) on line 1:5: 1\n"),
+        "{out}"
+    );
+    assert!(out.contains(": GetVar(0) on line 2:7: a\n"), "{out}");
+    assert!(out.contains(": Add on line 2:7: a + 2\n"), "{out}");
+}
+
+#[test]
+fn profile_counts_operations() {
+    let profile = |code: &str| {
+        let (mut output, mut report) = (Vec::new(), Vec::new());
+        let options = RunOptions {
+            profile: Some(&mut report),
+            ..RunOptions::default()
+        };
+        run_named(None, Rc::from(code), options, &mut output);
+        String::from_utf8_lossy(&report).to_string()
+    };
+    // Straight line code executes every instruction exactly once.
+    let report = profile("a = 1\nprint(a + 20)");
+    assert!(
+        report.contains("\nSetVar                 1  14.3% #######\n"),
+        "{report}"
+    );
+    assert!(report.ends_with("\nTotal                  7\n"), "{report}");
+    // 7 instructions before the loop, 12 per iteration plus one in the called
+    // function, and 4 for the final condition check.
+    let report = profile("f = fn(n) n\ni = 0\nwhile i < 10 i = f(i + 1)");
+    assert!(report.contains("\nFnCall                10 "), "{report}");
+    assert!(
+        report.ends_with(&format!("\n{:<12}{:>12}\n", "Total", 7 + 10 * (12 + 1) + 4)),
+        "{report}"
+    );
+}
+
+#[test]
+fn cached_run() {
+    let dir = std::env::temp_dir().join(format!("aoc-lang-cache-test-{}", std::process::id()));
+    let cache = crate::cache::Cache::new(dir.clone());
+    let code: Rc<str> =
+        Rc::from("make = fn(n) fn(x) x * n + [\"a\", 'b'][1]\nprint(make(2)(1.5), print_sep, nil)");
+    let run_cached = || {
+        let mut output = Vec::new();
+        let options = RunOptions {
+            cache: Some(&cache),
+            ..RunOptions::default()
+        };
+        run_named(
+            Some("cached.aoc".into()),
+            code.clone(),
+            options,
+            &mut output,
+        );
+        String::from_utf8_lossy(&output).to_string()
+    };
+    let want = run(&code);
+    assert_eq!(run_cached(), want);
+    assert_eq!(cache.hits(), 0);
+    assert_eq!(run_cached(), want);
+    assert_eq!(cache.hits(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn strict_with_other_options() {
+    let undeclared = "Assignment to undeclared variable x";
+    let dir = std::env::temp_dir().join(format!("aoc-lang-strict-test-{}", std::process::id()));
+    let cache = crate::cache::Cache::new(dir.clone());
+    let code: Rc<str> = Rc::from("x = 1\nprint(x)");
+    let run_with = |options: RunOptions| {
+        let mut output = Vec::new();
+        run_named(
+            Some("strict.aoc".into()),
+            code.clone(),
+            options,
+            &mut output,
+        );
+        String::from_utf8_lossy(&output).to_string()
+    };
+    let mut report = Vec::new();
+    let out = run_with(RunOptions {
+        profile: Some(&mut report),
+        ..strict_options()
+    });
+    assert!(out.contains(undeclared), "{out}");
+    let out = run_with(RunOptions {
+        debug: true,
+        ..strict_options()
+    });
+    assert!(out.contains(undeclared), "{out}");
+    // A cached chunk compiled without strict mode isn't reused by it.
+    let cached = || RunOptions {
+        cache: Some(&cache),
+        ..RunOptions::default()
+    };
+    assert_eq!(run_with(cached()), "1\n");
+    assert_eq!(run_with(cached()), "1\n");
+    let out = run_with(RunOptions {
+        strict: true,
+        ..cached()
+    });
+    assert!(out.contains(undeclared), "{out}");
+    assert_eq!(cache.hits(), 1);
+    let mut output = Vec::new();
+    assert!(!check_named(None, code.clone(), true, &mut output));
+    assert!(String::from_utf8_lossy(&output).contains(undeclared));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn cached_run_warnings() {
+    let dir = std::env::temp_dir().join(format!("aoc-lang-warning-test-{}", std::process::id()));
+    let cache = crate::cache::Cache::new(dir.clone());
+    let code: Rc<str> = Rc::from("let y = 1\nlet y = 2\nprint(y)");
+    let run_cached = || {
+        let mut output = Vec::new();
+        let options = RunOptions {
+            cache: Some(&cache),
+            ..RunOptions::default()
+        };
+        run_named(Some("warn.aoc".into()), code.clone(), options, &mut output);
+        String::from_utf8_lossy(&output).to_string()
+    };
+    for _ in 0..2 {
+        let output = run_cached();
+        assert!(output.contains("Warning: let y shadows"), "{output}");
+        assert!(output.ends_with("2\n"), "{output}");
+    }
+    assert_eq!(cache.hits(), 0);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn operator_overloading() {
+    let code = "Vec2 = fn(x, y) {
+    v = {=}
+    v.__type = \"vec2\"
+    v.x = x
+    v.y = y
+    v.__add = fn(a, b) Vec2(a.x + b.x, a.y + b.y)
+    v.__mul = fn(a, k) Vec2(a.x * k, a.y * k)
+    v.__eq = fn(a, b) a.x == b.x && a.y == b.y
+    v.__neg = fn(a) Vec2(-a.x, -a.y)
+    v
+}
+a = Vec2(1, 2) + Vec2(10, 20)
+print(a.x, \",\", a.y)
+b = a * 6
+print(b.x, \",\", b.y)
+print(a == Vec2(11, 22), a != Vec2(11, 22), a == b, -a == Vec2(-11, -22))
+plain = {=}
+plain.__add = fn(a, b) 42
+plain + 1";
+    let out = run(code);
+    assert!(
+        out.starts_with("11,22\n66,132\ntruefalsefalsetrue\n"),
+        "{out}"
+    );
+    assert!(out.contains("Unsupported Add for"), "{out}");
+}
+
+#[test]
+fn complex_numbers() {
+    let code = "i = complex(0, 1)
+z = complex(1, 2) + complex(3, -1)
+print(z, \" \", z - 1, \" \", z * 2.5, \" \", -z)
+print(complex(1, 2) * complex(3, 4), \" \", complex(-5, 10) / complex(1, 2))
+dir = complex(1, 0)
+pos = complex(0, 0)
+for step = 0; step < 4; step += 1 {
+    pos = pos + dir * 2
+    dir = dir * i
+}
+print(pos == complex(0, 0), dir == complex(1, 0), complex(0, 1) * i)
+print(real(z), imag(z), real(3))
+complex(1, 2) / 0";
+    let out = run(code);
+    assert!(
+        out.starts_with("4+1i 3+1i 10+2.5i -4-1i\n-5+10i 3+4i\ntruetrue-1+0i\n413\n"),
+        "{out}"
+    );
+    assert!(out.contains("Unsupported Div for 1+2i and 0"), "{out}");
+}
+
+#[test]
+fn rational_numbers() {
+    let code = "print(frac(1, 3) + frac(1, 6) == frac(1, 2), frac(1, 3) + frac(1, 6))
+print(frac(2, -4), \" \", frac(6, 3), \" \", frac(1, 3) * 3, \" \", 1 - frac(1, 4))
+print(frac(1, 2) / frac(3, 4), \" \", -frac(1, 2), \" \", frac(1, 2) + 0.25)
+print(frac(1, 3) < frac(1, 2), frac(2, 3) > 1, frac(1, 2) < 0.6, frac(4, 2) == 2)
+frac(1, 0)";
+    let out = run(code);
+    assert!(
+        out.starts_with("true1/2\n-1/2 2 1 3/4\n2/3 -1/2 0.75\ntruefalsetruetrue\n"),
+        "{out}"
+    );
+    assert!(out.contains("Fraction 1/0 has a zero denominator"), "{out}");
+}
+
+#[test]
+fn booleans() {
+    let code = "t = true
+print_sep(\" \", t, !t, 1 < 2, 2 <= 1, t == true, t == 1, false || nil)
+seen = {=}
+seen[true] = \"yes\"
+print_sep(\" \", seen[1 == 1], sort([true, false, true]), if false 1 else 2)
+kind = fn(f) try { f(); nil } catch e e.kind
+print_sep(\" \", kind(fn() true + 1), kind(fn() -false), kind(fn() 2 * true))";
+    assert_eq!(
+        run(code),
+        "true false true false true false nil\nyes [false, true, true] 2\ntype type type\n"
+    );
+}
+
+#[test]
+fn bit_shifts() {
+    let code = "print_sep(\" \", 1 << 4, 3 << 62, 1 << 64, -1 << 63, -1 << 64, (1 << 100) >> 98, 256 >> 4, -9 >> 1, 5 >> 64, -5 >> 1000)
+print(try 1 << -1 catch e e.kind + \": \" + e.message)
+print(try 1 >> -2 catch e e.message)
+print(try 1 << 100000000 catch e e.message)
+print(try 1.5 << 2 catch e e.message)
+x = 9223372036854775807 + 1
+print_sep(\" \", x >> 65, -x >> 65, x >> 63, (x << 31) >> 95)";
+    assert_eq!(
+        run(code),
+        "16 13835058055282163712 18446744073709551616 -9223372036854775808 -18446744073709551616 4 16 -5 0 -1\n\
+         value: Cannot shift by a negative number of bits, -1\n\
+         Cannot shift by a negative number of bits, -2\n\
+         Cannot shift left by 100000000 bits, the most is 16777216\n\
+         Unsupported LeftShift for 1.5 and 2\n\
+         0 -1 1 0\n"
+    );
+}
+
+#[test]
+fn xor() {
+    let code = "print_sep(\" \", 5 ^ 3, 6 ^ 6, -1 ^ 0, (1 ^ 2) == 3)
+print_sep(\" \", true ^ false, true ^ true, \"a\" ^ nil, [] ^ 0, 1 ^ true)
+x = 12
+x = x ^ 10 ^ 10
+print(x)
+big = 9223372036854775807 + 1
+print(try big ^ 1 catch e e.kind + \": \" + e.message)
+print(try 1 ^ big catch e e.message)
+print(try big ^ big catch e e.message)";
+    assert_eq!(
+        run(code),
+        "6 0 -1 true\ntrue false true false false\n12\n\
+         type: Unsupported XOr for 9223372036854775808 and 1\n\
+         Unsupported XOr for 1 and 9223372036854775808\n\
+         Unsupported XOr for 9223372036854775808 and 9223372036854775808\n"
+    );
+}
+
+#[test]
+fn bitwise_assignment() {
+    let code = "x = 3
+x <<= 2
+mask = 29
+mask &= 15
+flags = 8
+flags |= 3
+y = 5
+y ^= 1
+z = -64
+z >>= 3
+v = [1, 7]
+v[1] &= 5
+print_sep(\" \", x, mask, flags, y, z, v)
+print_sep(\" \", 6 & 3, 6 | 3, 1 | 2 == 3, 5 & 4 << 0, 12 & 10 | 1)
+print(try 1.5 & 1 catch e e.kind + \": \" + e.message)
+print(try \"a\" | 1 catch e e.message)";
+    assert_eq!(
+        run(code),
+        "12 13 11 4 -8 [1, 5]\n2 7 true 4 9\ntype: Unsupported BitAnd for 1.5 and 1\nUnsupported BitOr for a and 1\n"
+    );
+}
+
+#[test]
+fn repeat_negative_count() {
+    let code = "print_sep(\"|\", \"ab\" * 0, \"ab\" * 3, \"ab\" * -1, 2 * \"x\", -5 * \"x\")
+print_sep(\"|\", [1, 2] * 0, [1, 2] * 2, [1] * -3, 2 * [0])";
+    assert_eq!(run(code), "|ababab||xx|\n[]|[1, 2, 1, 2]|[]|[0, 0]\n");
+}
+
+#[test]
+fn repeat_huge_count() {
+    assert!(run("[1, 2] * 9223372036854775807")
+        .contains("Cannot repeat a length of 2 9223372036854775807 times, the most is 16777216"));
+    assert!(run("\"ab\" * (1 << 24)").contains("Cannot repeat a length of 2"));
+    assert!(run("(1 << 40) * [0]").contains("Cannot repeat a length of 1"));
+    let code =
+        "print(+([] * 9223372036854775807), +(\"\" * 9223372036854775807), +(\"a\" * (1 << 24)))
+print(try [1] * (1 << 25) catch e e.kind)";
+    assert_eq!(run(code), "0016777216\nvalue\n");
+}
+
+#[test]
+fn index_out_of_range() {
+    let code = "v = [1, 2, 3]
+print(try v[-4] catch e e.kind + \": \" + e.message)
+print(try v[-9223372036854775807 - 1] catch e e.message)
+print(try v[1, 5] catch e e.message)
+print(try v[2, 1] catch e e.message)
+print(try \"abc\"[-5, 2] catch e e.message)
+print(try \"\u{e9}\"[0, 1] catch e e.message)
+print(try bytes(\"ab\")[0, 3] catch e e.message)
+print(try v[3] = 0 catch e e.message)
+print(try v[-4] = 0 catch e e.message)
+print(v[-3, 3], v[3, 3])";
+    assert_eq!(
+        run(code),
+        "index: Index -4 out of range for vector of length 3\n\
+         Index -9223372036854775808 out of range for vector of length 3\n\
+         Slice [1, 5] out of range for vector of length 3\n\
+         Slice [2, 1] out of range for vector of length 3\n\
+         Slice [-5, 2] out of range for string of length 3\n\
+         Slice [0, 1] out of range for string of length 2\n\
+         Slice [0, 3] out of range for bytes of length 2\n\
+         Index 3 out of range for vector of length 3\n\
+         Index -4 out of range for vector of length 3\n\
+         [1, 2, 3][]\n"
+    );
+}
+
+#[test]
+fn floor_modulo() {
+    let code = "print_sep(\" \", 7 % 3, -7 % 3, 7 % -3, -7 % -3, -6 % 3, 6 % -3)
+print_sep(\" \", -7.5 % 2, 7.5 % -2, -1 % 2.5, -7 % 2.0)
+min = -9223372036854775807 - 1
+print_sep(\" \", min % -1, min % 10, -(1 << 70) % 1000, (1 << 70) % -1000)
+ok = true
+for a in [-7, -3, 0, 4, 9] for b in [-4, -1, 2, 5] ok = ok && a == (a // b) * b + a % b
+print(ok)
+print(try -1 % 0 catch e e.kind)
+print(try -1.5 % 0.0 catch e e.kind)";
+    assert_eq!(
+        run(code),
+        "1 2 -2 -1 0 0\n0.5 -0.5 1.5 1\n0 2 576 -576\ntrue\nzero_division\nzero_division\n"
+    );
+}
+
+#[test]
+fn floor_division() {
+    let code = "print_sep(\" \", 7 / 2, 7 // 2, -7 // 2, 7 // -2, -7 // -2, 6 / 3 == 2.0)
+print_sep(\" \", 7.5 // 2, -7.5 // 2, frac(7, 2) // 1, frac(-7, 2) // 1)
+min = -9223372036854775807 - 1
+print(min // -1)
+x = 17
+x //= 5
+print(x)
+print(try 1 // 0 catch e e.kind)";
+    assert_eq!(
+        run(code),
+        "3.5 3 -4 -4 3 true\n3 -4 3 -4\n9223372036854775808\n3\nzero_division\n"
+    );
+}
+
+#[test]
+fn division_matrix() {
+    // `/` is true division for every pair of number types, `//` rounds
+    // towards negative infinity.
+    let code = "print_sep(\" \", 7 / 2, -7 / 2, 7 / -2, -7 / -2, 6 / 3)
+print_sep(\" \", 7.0 / 2, 7 / 2.0, -7.0 / 2.0, 1 / 4.0)
+print_sep(\" \", 7 // 2, -7 // 2, 7 // -2, -7 // -2, 6 // 3)
+print_sep(\" \", 7.0 // 2, 7 // 2.0, -7.0 // 2.0, 1 // 4.0)
+print_sep(\" \", frac(1, 2) / 2, 3 / frac(3, 4), frac(1, 2) / 0.5, frac(-7, 2) // 2)
+print_sep(\" \", complex(4, 2) / 2, 1 / complex(0, 1))
+big = 9223372036854775807 * 4
+print_sep(\" \", big / 8, big // 8, -big // 8, big // -3)
+kind = fn(f) try { f(); nil } catch e e.kind
+print_sep(\" \", kind(fn() 1 / 0), kind(fn() 1.0 / 0), kind(fn() 1 // 0.0), kind(fn() big // 0), kind(fn() frac(1, 2) / 0), kind(fn() \"a\" // 2))";
+    assert_eq!(
+        run(code),
+        "3.5 -3.5 -3.5 3.5 2\n\
+         3.5 3.5 -3.5 0.25\n\
+         3 -4 -4 3 2\n\
+         3 3 -4 0\n\
+         1/4 4 1 -2\n\
+         2+1i 0-1i\n\
+         4611686018427388000 4611686018427387903 -4611686018427387904 -12297829382473034410\n\
+         zero_division zero_division zero_division zero_division zero_division type\n"
+    );
+}
+
+#[test]
+fn big_integers() {
+    let code = "fact = fn(n) if n < 2 1 else n * fact(n - 1)
+big = fact(25)
+print(big)
+print(fact(20), \" \", big // fact(23), \" \", big % 1000000007, \" \", -big + big)
+print(big > fact(20), fact(20) < big, big == fact(25), big + 1 == big, -big < 0)
+m = 9223372036854775807
+print(m + 1, \" \", -m - 2, \" \", (m + 1) - 1, \" \", big * 1.0)
+seen = {=}
+seen[m + 1] = 1
+print(seen[m + 2 - 1])";
+    assert_eq!(
+        run(code),
+        "15511210043330985984000000\n\
+         2432902008176640000 600 440732388 0\n\
+         truetruetruefalsetrue\n\
+         9223372036854775808 -9223372036854775809 9223372036854775807 15511210043330986000000000\n\
+         1\n"
+    );
+}
+
+#[test]
+fn big_literals() {
+    let code = "x = 123456789012345678901234567890
+print(99999999999999999999, \" \", x // 10000000000, \" \", x % 11)
+print(9223372036854775808 - 1 == 9223372036854775807, \" \", -9223372036854775808)";
+    assert_eq!(
+        run(code),
+        "99999999999999999999 12345678901234567890 7\ntrue -9223372036854775808\n"
+    );
+    let expr = crate::aoc::parse("print(99999999999999999999)").unwrap();
+    assert_eq!(expr.format(), "print(99999999999999999999)\n");
+}
+
+#[test]
+fn range_vectors() {
+    let code = "print(range(5), range(2, 5), range(0, 10, 3))
+print(range(5, 0, -2), range(-1, -4, -1), range(-3), range(5, 2), range(2, 5, -1))
+r = range(3)
+print(r[1], \" \", +r, \" \", r[-1], \" \", r == [0, 1, 2])
+r << 3
+print(r)
+range = fn(n) [n]
+for x in range(7) print(x)";
+    assert_eq!(
+        run(code),
+        "[0, 1, 2, 3, 4][2, 3, 4][0, 3, 6, 9]\n\
+         [5, 3, 1][-1, -2, -3][][][]\n\
+         1 3 2 true\n\
+         [0, 1, 2, 3]\n\
+         7\n"
+    );
+    assert!(run("range(0, 5, 0)")
+        .contains("range expects 1 to 3 integers and a non zero step, not (0, 5, 0)"));
+    assert!(run("for i in range(0, 5, 0) 1")
+        .contains("range expects 1 to 3 integers and a non zero step, not (0, 5, 0)"));
+}
+
+#[test]
+fn for_in() {
+    let code = "s = 0
+for i in range(1000000000000000) {
+    if i == 5 break
+    s += i
+}
+print(s)
+last = for [a, b] in [[1, 2], [3, 4]] a * b
+print(last)
+for c in \"ab\" print(c)
+for i in range(10, 0, -4) print(i) else print(\"done\")
+counter = fn(n) {
+    it = {=}
+    it.next = fn() if n > 0 n -= 1 else nil
+    it
+}
+outer: for x in counter(3)
+    for y in range(x, 10)
+        if y == 1 break outer else print(x, y)
+print(range(3))
+for x in 5 1";
+    let out = run(code);
+    assert!(
+        out.starts_with(
+            "10\n12\n97\n98\n10\n6\n2\ndone\n22\n23\n24\n25\n26\n27\n28\n29\n[0, 1, 2]\n"
+        ),
+        "{out}"
+    );
+    assert!(out.contains("Cannot iterate over 5"), "{out}");
+
+    let code = "r = range(3)
+s = 0
+for x in r s += x
+for x in r s += 10 * x
+times 2 for x in r s += 100 * x
+print(s, \" \", r)";
+    assert_eq!(run(code), "633 [0, 1, 2]\n");
+}
+
+#[test]
+fn times() {
+    assert_eq!(run("times 3 { print(\"x\") }"), "x\nx\nx\n");
+    let code = "n = 0
+times -2 n += 1
+times 0 n += 1
+print(n)
+range = 2
+outer: times range times 5 {
+    n += 1
+    if n == 3 break outer
+}
+print(n)
+times 1.5 print(n)";
+    let out = run(code);
+    assert!(out.starts_with("0\n3\n"), "{out}");
+    assert!(
+        out.contains("range expects 1 to 3 integers and a non zero step, not (1.5)"),
+        "{out}"
+    );
+}
+
+#[test]
+fn numbers() {
+    assert_eq!(
+        run("print(numbers(\"3.5 and -2.0\", \"f\"))"),
+        "[3.5, -2]\n"
+    );
+    assert_eq!(run("print(numbers(\"a=-4 b=7\"))"), "[-4, 7]\n");
+    assert_eq!(run("print(numbers(\"x-4..12\", \"u\"))"), "[4, 12]\n");
+    assert_eq!(run("print(numbers(\"none\"))"), "[]\n");
+    assert!(run("numbers(\"1\", \"x\")").contains("Unknown numbers flag 'x'"));
+}
+
+#[test]
+fn find_all() {
+    assert_eq!(
+        run("print(find_all(\"abcabcaaa\", \"a\"))"),
+        "[0, 3, 6, 7, 8]\n"
+    );
+    assert_eq!(run("print(find_all(\"aaaa\", \"aa\"))"), "[0, 2]\n");
+    assert_eq!(run("print(find_all(\"abc\", \"x\"))"), "[]\n");
+    assert!(run("find_all(\"abc\", \"\")").contains("cannot search for an empty string"));
+}
+
+#[test]
+fn split_join() {
+    let code = "parts = split(\"1,2,,3\", \",\")
+print_sep(\" \", parts, +parts, +parts[2], split(\"abc\", \"\"), split(\"\", \",\"), split(\"é!\", \"\"))
+print(join(parts, \";\"), \"|\", join([], \",\"), \"|\", join([\"x\"], \",\"))
+print(join(split(\"a b c\", \" \"), \"\") == \"abc\")";
+    assert_eq!(
+        run(code),
+        "[\"1\", \"2\", \"\", \"3\"] 4 0 [\"a\", \"b\", \"c\"] [\"\"] [\"é\", \"!\"]\n1;2;;3||x\ntrue\n"
+    );
+    assert!(run("join([\"a\", 1], \",\")")
+        .contains("join expects a vector of strings, but element 1 is 1"));
+    assert!(run("split(1, \",\")").contains("split expects two strings, not 1 and ,"));
+}
+
+#[test]
+fn map_filter_reduce() {
+    let code = "print(map([1, 2, 3], fn(x) x * x), filter(range(6), fn(x) x % 2), map(\"ab\", fn(c) c + 1))
+print(reduce([1, 2, 3], fn(acc, x) acc + x, 10), reduce([], fn(a, b) a + b, 0), map([[1], [2, 3]], len))
+v = [1, 2]
+print(map(v, fn(x) v << x), v)
+use \"std\" { sum, count }
+print(sum([1, 2, 3]), count([1, 2, 3], fn(x) x > 1))";
+    assert_eq!(
+        run(code),
+        "[1, 4, 9][1, 3, 5][98, 99]\n160[1, 2]\n[1, 2][1, 2, 1, 2]\n62\n"
+    );
+    assert!(run("map([1], 1)").contains("map expects a function, not 1"));
+    assert!(
+        run("filter([], fn(a, b) a)").contains("filter expects a function of 1 arguments, not 2")
+    );
+    assert!(
+        run("reduce([1], fn(a) a, 0)").contains("reduce expects a function of 2 arguments, not 1")
+    );
+    assert!(run("reduce([1], 0, fn(a, b) a)").contains("reduce expects a function, not 0"));
+    assert!(run("map(1, fn(x) x)").contains("Cannot iterate over 1"));
+}
+
+#[test]
+fn zip_unzip() {
+    let code = "print(unzip([[1, \"a\"], [2, \"b\"]]))
+print(zip([1, 2, 3], [4, 5]), zip([], [1]), unzip([]))
+pairs = [[1, 2], [3, 4]]
+print(unzip(pairs) == [[1, 3], [2, 4]], zip(unzip(pairs)[0], unzip(pairs)[1]) == pairs)";
+    assert_eq!(
+        run(code),
+        "[[1, 2], [\"a\", \"b\"]]\n[[1, 4], [2, 5]][][[], []]\ntruetrue\n"
+    );
+    assert!(run("unzip([[1, 2], [3]])")
+        .contains("unzip expects a vector of pairs, but element 1 is [3]"));
+    assert!(
+        run("unzip([[1, 2], 3])").contains("unzip expects a vector of pairs, but element 1 is 3")
+    );
+    assert!(run("unzip(1)").contains("unzip expects a vector of pairs, not 1"));
+    assert!(run("zip([1], 2)").contains("zip expects two vectors, not [1] and 2"));
+}
+
+#[test]
+fn zip_with() {
+    let code = "a = [1, 2, 3]
+b = [10, 20, 30, 40]
+c = zip_with(a, b, fn(x, y) x + y)
+print(c)
+print(zip_with([], b, fn(x, y) x))
+zip_with(a, b, 5)";
+    let out = run(code);
+    assert!(out.starts_with("[11, 22, 33]\n[]\n"), "{out}");
+    assert!(out.contains("Only functions can be called"), "{out}");
+}
+
+#[test]
+fn repr() {
+    let code = "o = {=}
+o[\"a b\"] = \"x\"
+print(o)
+print(repr(o))
+o = {=}
+o[1] = [\"x\\y\nz\", nil]
+print(repr(o))
+print(repr(\"a\"), repr(1.5))
+print(\"a\", [\"a\"], repr([\"a\"]))";
+    assert_eq!(
+        run(code),
+        "{=\"a b\": \"x\"}\n{=\"a b\": \"x\"}\n{=1: [\"x\\\\y\\nz\", nil]}\n\"a\"1.5\na[\"a\"][\"a\"]\n"
+    );
+}
+
+#[test]
+fn wrapping_arithmetic() {
+    let code = "max = 9223372036854775807
+print(max + 1)
+print(wadd(max, 1))
+print(wsub(-max - 1, 1))
+print(wmul(max, 2))
+print(wmul(3, 4))
+h = 0
+for c in \"abc\" h = wadd(wmul(h, 31), c)
+print(h)
+wadd(1.5, 1)";
+    let out = run(code);
+    assert!(
+        out.starts_with(
+            "9223372036854775808\n-9223372036854775808\n9223372036854775807\n-2\n12\n96354\n"
+        ),
+        "{out}"
+    );
+    assert!(
+        out.contains("wadd expects two integers, not 1.5 and 1"),
+        "{out}"
+    );
+}
+
+#[test]
+fn defaultdict() {
+    let code = "g = defaultdict(\".\")
+print(g[[3, 4]])
+g[[3, 4]] = \"#\"
+print(g[[3, 4]], g[[0, 0]])
+print(grid_get(g, 1, 2))
+rows = defaultdict([])
+row = rows[0]
+row += [1]
+print(rows[0], row)
+adj = defaultdict([])
+adj.a << \"b\"
+adj.a << \"c\"
+adj[\"b\"] << \"a\"
+print(adj.a, adj.b, \" \", len(adj))
+counts = defaultdict(0)
+counts.x += 1
+counts.x += 1
+print(counts)
+print(counts.y, \" \", len(counts), \" \", defaultdict(1) == {=})";
+    assert_eq!(
+        run(code),
+        ".\n#.\n.\n[][1]\n[\"b\", \"c\"][\"a\"] 2\n{=\"x\": 2}\n0 2 true\n"
+    );
+}
+
+#[test]
+fn assert_eq() {
+    assert_eq!(
+        run("print(assert_eq([1, \"a\"], [1, \"a\"]))\nassert_eq(frac(2, 4), frac(1, 2))"),
+        "nil\n"
+    );
+    let lines: Vec<_> = run("part1 = 6 * 7\nassert_eq(part1, \"42\")")
+        .lines()
+        .map(String::from)
+        .collect();
+    assert_eq!(lines[1], "RuntimeError: assert_eq failed: 42 != \"42\"");
+    assert!(lines[2].starts_with("on line 2:1:"), "{lines:?}");
+}
+
+#[test]
+fn error_kinds() {
+    let code = "kind = fn(f) try { f(); nil } catch e e.kind
+print_sep(\" \", kind(fn() 1 / 0), kind(fn() 1 % 0.0), kind(fn() frac(1, 0)), kind(fn() \"a\" / 2))
+print_sep(\" \", kind(fn() [1][2]), kind(fn() \"a\"[5]), kind(fn(x) x), kind(fn() range()))
+print_sep(\" \", kind(fn() [a, b] = [1]), kind(fn() error(\"no\")), kind(fn() assert_eq(1, 2)))
+e = try nil + 1 catch e e
+print(e.kind, \": \", e.message)";
+    assert_eq!(
+        run(code),
+        "zero_division zero_division zero_division type\n\
+index index call type\n\
+value user assert\n\
+type: Unsupported Add for nil and 1\n"
+    );
+}
+
+#[test]
+fn custom_error() {
+    let code = "parse = fn(s) if s == \"\" error(\"empty input\") else s
+print(try parse(\"\") catch e \"caught: \" + e.message, \" \", try parse(\"ok\") catch e nil)
+f = fn() parse(\"\")
+f()";
+    let lines: Vec<_> = run(code).lines().map(String::from).collect();
+    assert_eq!(lines[0], "caught: empty input ok");
+    assert_eq!(lines[2], "RuntimeError: empty input");
+    assert!(lines[3].starts_with("on line 1:26:"), "{lines:?}");
+    assert!(lines[5].starts_with("on line 4:1:"), "{lines:?}");
+}
+
+#[test]
+fn new_vec() {
+    let code = "v = new_vec(3, [])
+v[0] << 1
+v[2] << 2
+g = new_grid(2, 3, {=})
+g[1][2].x = 5
+row = new_vec(2, 0)
+row[1] = 7
+print(v, \" \", g, \" \", row, \" \", new_vec(0, 1), \" \", new_grid(2, 0, 1))";
+    assert_eq!(
+        run(code),
+        "[[1], [], [2]] [[{=}, {=}, {=}], [{=}, {=}, {=\"x\": 5}]] [0, 7] [] [[], []]\n"
+    );
+    assert!(run("new_vec(-1, 0)").contains("new_vec expects a non negative length, not -1"));
+    assert!(run("new_grid(1, \"a\", 0)").contains("new_grid expects an integer length, not a"));
+}
+
+#[test]
+fn scan() {
+    let code = "print(scan([1, 2, 3], 0, fn(acc, x) acc + x))
+print(scan([], 5, fn(acc, x) acc * x), scan([\"a\", \"b\"], \"\", fn(acc, x) acc + x))";
+    assert_eq!(run(code), "[0, 1, 3, 6]\n[5][\"\", \"a\", \"ab\"]\n");
+    assert!(run("scan(1, 0, fn(a, x) a)").contains("scan expects a vector, not 1"));
+}
+
+#[test]
+fn partition() {
+    let code = "print(partition([1, 2, 3, 4], fn(x) x % 2 == 0))
+[long, short] = partition([\"abc\", \"d\", \"ef\", \"ghi\"], fn(s) +s > 2)
+print(long, short, partition([], fn(x) 1))";
+    assert_eq!(
+        run(code),
+        "[[2, 4], [1, 3]]\n[\"abc\", \"ghi\"][\"d\", \"ef\"][[], []]\n"
+    );
+}
+
+#[test]
+fn count_if() {
+    let code =
+        "print_sep(\" \", count_if([1, 2, 3, 4, 6], fn(x) x % 2 == 0), count_if([], fn(x) 1))
+print(try count_if([1, \"a\"], fn(x) x + 1 > 0) catch e e.message)";
+    assert_eq!(run(code), "3 0\nUnsupported Add for a and 1\n");
+    assert!(run("count_if(1, fn(x) x)").contains("count_if expects a vector, not 1"));
+}
+
+#[test]
+fn sort_desc() {
+    let code = "v = [3, 1, 2]
+print(sort_desc(v), sort(v), v)
+print(sort_desc([[1, 2], [1], [0, 5]]), sort([frac(1, 2), 0.25, 1]), sort_desc([]))";
+    assert_eq!(
+        run(code),
+        "[3, 2, 1][1, 2, 3][3, 1, 2]\n[[1, 2], [1], [0, 5]][0.25, 1/2, 1][]\n"
+    );
+    assert!(run("sort_desc([1, \"a\"])").contains("RuntimeError: Cannot compare"));
+    assert!(run("sort(1)").contains("sort expects a vector, not 1"));
+}
+
+#[test]
+fn max_min_by() {
+    let code = "nodes = [{= \"name\": \"a\", \"score\": 3}, {= \"name\": \"b\", \"score\": 7}, {= \"name\": \"c\", \"score\": 7}, {= \"name\": \"d\", \"score\": -1}]
+best = max_by(nodes, fn(n) n[\"score\"])
+print_sep(\" \", best[0][\"name\"], best[1], same(best[0], nodes[1]))
+worst = min_by(nodes, fn(n) n[\"score\"])
+print_sep(\" \", worst[0][\"name\"], worst[1])
+calls = 0
+print(max_by([3, 1, 2], fn(x) { calls += 1; -x }), min_by([5], fn(x) x * 1.5), calls)";
+    assert_eq!(run(code), "b 7 true\nd -1\n[1, -1][5, 7.5]3\n");
+    assert!(run("max_by([], fn(x) x)").contains("max_by of an empty vector"));
+    assert!(run("min_by([1, 2], fn(x) if x == 1 \"a\" else 2)").contains("Cannot compare 2 and a"));
+    assert!(
+        run("max_by([1], fn(a, b) a)").contains("max_by expects a function of 1 arguments, not 2")
+    );
+    assert!(run("max_by(1, fn(x) x)").contains("max_by expects a vector, not 1"));
+}
+
+#[test]
+fn sort_comparator() {
+    let code = "print(sort([\"ccc\", \"a\", \"bb\"], fn(a, b) +a - +b), sort_desc([1, 3, 2], fn(a, b) a - b))
+pairs = [[1, \"x\"], [0, \"y\"], [1, \"a\"], [0, \"b\"]]
+print(sort(pairs, fn(a, b) a[0] - b[0]))
+print(sort([1, 2, 3, 4, 5], fn(a, b) 1), sort([0.5, 0.25], fn(a, b) a - b), sort([], fn(a, b) 0))
+calls = 0
+sort([5, 1, 4, 2, 3], fn(a, b) { calls += 1; a - b })
+print(calls > 0 && calls <= 10)";
+    assert_eq!(
+        run(code),
+        "[\"a\", \"bb\", \"ccc\"][3, 2, 1]\n\
+         [[0, \"y\"], [0, \"b\"], [1, \"x\"], [1, \"a\"]]\n\
+         [1, 2, 3, 4, 5][0.25, 0.5][]\n\
+         true\n"
+    );
+    assert!(run("sort([1, 2], fn(a, b) \"x\")")
+        .contains("sort expects a comparator returning a number, not x"));
+    assert!(run("sort([1, 2], fn(a) 0)").contains("sort expects a function of 2 arguments, not 1"));
+    assert!(run("sort([1, 2], 3)").contains("sort expects a function, not 3"));
+    assert!(run("sort([1, \"a\"])").contains("Cannot compare"));
+    assert!(run("sort([1, 2], fn(a, b) error(\"boom\"))").contains("boom"));
+}
+
+#[test]
+fn conversions() {
+    let code = "print_sep(\" \", int(\"42\"), int(\" -7 \"), int(3.9), int(-3.9), int(frac(7, 2)), int(true))
+print_sep(\" \", float(\"1.5\"), float(2), float(frac(1, 4)), float(\"-3\"), float(false))
+print_sep(\" \", str(12) + str(1.5), str([1, \"a\"]), str(nil), str(\"x\") == \"x\")
+nums = []
+for line in split(\"3,14,-1\", \",\") nums << int(line)
+print(nums, +nums[0] + nums[1])";
+    assert_eq!(
+        run(code),
+        "42 -7 3 -3 3 1\n1.5 2 0.25 -3 0\n121.5 [1, \"a\"] nil true\n[3, 14, -1]17\n"
+    );
+    assert!(run("int(\"abc\")").contains("Cannot convert \"abc\" to int"));
+    assert!(run("int(\"1.5\")").contains("Cannot convert \"1.5\" to int"));
+    assert!(run("float(\"x\")").contains("Cannot convert \"x\" to float"));
+    assert!(run("int(float(\"1e300\"))").contains("Cannot convert"));
+    assert!(run("int([1])").contains("int expects a number or a string, not [1]"));
+}
+
+#[test]
+fn type_names() {
+    let code = "print(join(map([nil, true, 1, 1 << 70, 1.5, frac(1, 2), complex(1, 2), \"a\", bytes(\"a\"), [], {=}, fn(x) x, len, range(3)], type), \" \"))
+print(type(type(1)) == \"str\")";
+    assert_eq!(
+        run(code),
+        "nil bool int int float frac complex str bytes vec obj fn fn vec\ntrue\n"
+    );
+    assert!(run("type()").contains("type expects 1 arguments, but got 0"));
+}
+
+#[test]
+fn set_operations() {
+    let code = "print(union([1, 2, 2], [3, 1]), intersect([1, 2, 3, 2], [3, 2]), diff([1, 2, 3, 1], [2]))
+print(union([], []), intersect([1], []), diff([[1, 2], [3]], [[3]]), union([\"a\"], [\"b\", \"a\"]))";
+    assert_eq!(
+        run(code),
+        "[1, 2, 3][2, 3][1, 3]\n[][][[1, 2]][\"a\", \"b\"]\n"
+    );
+    assert!(run("diff([1], 1)").contains("diff expects two vectors, not [1] and 1"));
+}
+
+#[test]
+fn rotate_grid() {
+    let code = "g = [[1, 2, 3], [4, 5, 6]]
+print(rotate_cw(g), rotate_ccw(g))
+print(flip_h(g), flip_v(g), g)
+print(rotate_cw(rotate_cw(rotate_cw(rotate_cw(g)))) == g, rotate_cw([]), flip_h([[]]))";
+    assert_eq!(
+        run(code),
+        "[[4, 1], [5, 2], [6, 3]][[3, 6], [2, 5], [1, 4]]\n\
+[[3, 2, 1], [6, 5, 4]][[4, 5, 6], [1, 2, 3]][[1, 2, 3], [4, 5, 6]]\n\
+true[][[]]\n"
+    );
+    assert!(run("rotate_cw([[1, 2], [3]])").contains("rotate_cw expects rows of the same length"));
+    assert!(run("flip_v([1])").contains("flip_v expects a vector of rows, not [1]"));
+}
+
+#[test]
+fn bfs() {
+    let code = "graph = {=}
+graph.a = [\"b\", \"c\"]
+graph.b = [\"d\", \"a\"]
+graph.c = [\"d\"]
+graph.d = [\"e\"]
+graph.e = []
+graph.x = [\"a\"]
+dist = bfs(\"a\", fn(node) graph[node])
+print_sep(\" \", dist.a, dist.b, dist.c, dist.d, dist.e, dist.x)
+walls = [\"..#\", \"#..\", \"...\"]
+steps = fn(p) {
+    [x, y] = p
+    result = []
+    for d in [[0, 1], [1, 0], [0, -1], [-1, 0]] {
+        [nx, ny] = [x + d[0], y + d[1]]
+        if 0 <= nx && nx < 3 && 0 <= ny && ny < 3 if walls[ny][nx] != 35 result << [nx, ny]
+    }
+    result
+}
+grid = bfs([0, 0], steps)
+print_sep(\" \", grid[[2, 2]], grid[[2, 1]], grid[[2, 0]])";
+    assert_eq!(run(code), "0 1 1 2 3 nil\n4 3 nil\n");
+    assert!(run("bfs(1, fn(n) n)").contains("bfs neighbors function has to return a vector, not 1"));
+}
+
+#[test]
+fn dijkstra() {
+    let code = "graph = {=}
+graph.a = [[\"b\", 7], [\"c\", 9], [\"f\", 14]]
+graph.b = [[\"a\", 7], [\"c\", 10], [\"d\", 15]]
+graph.c = [[\"a\", 9], [\"b\", 10], [\"d\", 11], [\"f\", 2]]
+graph.d = [[\"b\", 15], [\"c\", 11], [\"e\", 6]]
+graph.e = [[\"d\", 6], [\"f\", 9]]
+graph.f = [[\"a\", 14], [\"c\", 2], [\"e\", 9]]
+graph.x = []
+dist = dijkstra(\"a\", fn(node) graph[node])
+print_sep(\" \", dist.a, dist.b, dist.c, dist.d, dist.e, dist.f, dist.x)";
+    assert_eq!(run(code), "0 7 9 20 20 11 nil\n");
+    assert!(run("dijkstra(0, fn(n) [[1, -1]])").contains("non negative integer cost, not [1, -1]"));
+    assert!(run("dijkstra(0, fn(n) [1])").contains("[node, cost] pairs"));
+}
+
+#[test]
+fn std_path_override() {
+    let dir = std::env::temp_dir().join(format!("aoc-std-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("std.aoc"), "hello = fn() \"custom std\"").unwrap();
+    assert_eq!(
+        crate::expr::std_source(Some(dir.as_os_str())).unwrap(),
+        "hello = fn() \"custom std\""
+    );
+    assert!(crate::expr::std_source(None)
+        .unwrap()
+        .contains("sum = fn(v)"));
+    assert!(crate::expr::std_source(Some(dir.join("missing").as_os_str())).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn strict_std() {
+    let code = "use \"std\" { sum, max }
+let std = use \"std\"
+print(sum([1, 2, 3]), \" \", max([4, 7, 5]), \" \", std.count([1, 2], fn(x) x > 1))";
+    assert_eq!(run_strict(code), "6 7 1\n");
+}
+
+#[test]
+fn printf() {
+    let code = "printf(\"%d|%s|%f|%x|%.2f|%x|100%%\", 42, \"hi\", 1.5, 255, frac(1, 3), -26)
+x = printf(\"
+%s %d %s\", [1, \"a\"], 9223372036854775807 * 10, nil)
+print(\"\")
+print(repr(x))";
+    assert_eq!(
+        run(code),
+        "42|hi|1.5|ff|0.33|-1a|100%\n[1, \"a\"] 92233720368547758070 nil\n\"\\n[1, \\\"a\\\"] 92233720368547758070 nil\"\n"
+    );
+    let err = |code| run(code).lines().nth(1).unwrap().to_string();
+    assert_eq!(
+        err("printf(\"%d\", 1, 2)"),
+        "RuntimeError: printf format \"%d\" expects 1 arguments, but got 2"
+    );
+    assert_eq!(
+        err("printf(\"%d %d\", 1)"),
+        "RuntimeError: printf format \"%d %d\" needs more than 1 arguments"
+    );
+    assert_eq!(
+        err("printf(\"%d\", 1.5)"),
+        "RuntimeError: printf %d expects an integer, not 1.5"
+    );
+    assert_eq!(
+        err("printf(\"%q\", 1)"),
+        "RuntimeError: Unknown printf placeholder %q"
+    );
+}
+
+#[test]
+fn insert_remove_at() {
+    let code = "v = [1, 2, 3]
+print(insert(v, 1, 10), v)
+insert(v, 4, 4)
+insert(v, -1, 9)
+insert(v, 0, 0)
+print(v)
+print(remove_at(v, 0), remove_at(v, -1), remove_at(v, 1), v)";
+    assert_eq!(
+        run(code),
+        "10[1, 10, 2, 3]\n[0, 1, 10, 2, 3, 9, 4]\n0410[1, 2, 3, 9]\n"
+    );
+    assert!(run("insert([1], 2, 0)").contains("Index 2 out of range for vector of length 1"));
+    assert!(run("remove_at([1], 1)").contains("Index 1 out of range for vector of length 1"));
+    assert!(run("remove_at([1], -2)").contains("Index -2 out of range for vector of length 1"));
+    assert!(run("remove_at([], 0)").contains("Index 0 out of range for vector of length 0"));
+    assert!(
+        run("insert(1, 0)").contains("insert expects a vector, an index and a value, not (1, 0)")
+    );
+}
+
+#[test]
+fn rotate() {
+    let code = "v = [1, 2, 3, 4]
+print(rotate(v, 1), rotate(v, -1), rotate(v, 6), rotate(v, -9), rotate(v, 0), v)
+print(rotate([], 3), rotate([1], -1))";
+    assert_eq!(
+        run(code),
+        "[2, 3, 4, 1][4, 1, 2, 3][3, 4, 1, 2][4, 1, 2, 3][1, 2, 3, 4][1, 2, 3, 4]\n[][1]\n"
+    );
+    assert!(
+        run("rotate([1], 1.5)").contains("rotate expects a vector and an integer, not [1] and 1.5")
+    );
+}
+
+#[test]
+fn swap() {
+    let code = "v = [1, 2, 3, 4]
+w = v
+print(swap(v, 0, 3), w)
+swap(w, -1, 1)
+swap(w, 2, 2)
+print(v)";
+    assert_eq!(run(code), "[4, 2, 3, 1][4, 2, 3, 1]\n[4, 1, 3, 2]\n");
+    assert!(run("swap([1, 2], 0, 2)").contains("Index 2 out of range for vector of length 2"));
+    assert!(run("swap([1, 2], -3, 0)").contains("Index -3 out of range for vector of length 2"));
+    assert!(run("swap(1, 0, 0)").contains("swap expects a vector, not 1"));
+}
+
+#[test]
+fn len() {
+    let code = "print_sep(\" \", len([1, 2, 3]), len([]), len(\"hello\"), len(\"\"), len({= \"a\": 1, \"b\": 2}), len({=}))
+v = [1]
+v << 2
+print(len(v) == +v)";
+    assert_eq!(run(code), "3 0 5 0 2 0\ntrue\n");
+    assert!(run("len(fn() 1)").contains("len expects a string, bytes, vector or object, not"));
+    assert!(run("len(nil)").contains("len expects a string, bytes, vector or object, not nil"));
+    assert!(run("len(1, 2)").contains("len expects 1 arguments, but got 2"));
+}
+
+#[test]
+fn bytes() {
+    let code = "b = bytes(\"héllo\")
+print_sep(\" \", b, +b, len(b), b[0], b[-1], b[1, 3], decode(b))
+c = bytes([0, 34, 92, 126, 255])
+print_sep(\" \", c, c[4], c + bytes(\"!\"), bytes(\"\"), !bytes(\"\"))
+print_sep(\" \", bytes(\"ab\") == bytes([97, 98]), bytes(\"ab\") == \"ab\", bytes(\"a\") < bytes(\"b\"))
+sum = 0
+for x in bytes(\"abc\") sum += x
+print(sum)
+o = {=}
+o[bytes(\"k\")] = 1
+print(o[bytes(\"k\")])";
+    assert_eq!(
+        run(code),
+        "b\"h\\xc3\\xa9llo\" 6 6 104 111 b\"\\xc3\\xa9\" héllo\n\
+         b\"\\x00\\\"\\\\~\\xff\" 255 b\"\\x00\\\"\\\\~\\xff!\" b\"\" true\n\
+         true false true\n294\n1\n"
+    );
+    assert!(run("bytes(\"ab\")[2]").contains("Bytes index 2 out of range for bytes of length 2"));
+    assert!(run("bytes([256])").contains("bytes expects integers from 0 to 255, not 256"));
+    assert!(run("bytes(1)").contains("bytes expects a string or a vector, not 1"));
+    assert!(run("decode(bytes([255]))").contains("decode got invalid UTF-8"));
+    assert!(run("decode(\"a\")").contains("decode expects bytes, not a"));
+}
+
+#[test]
+fn same() {
+    let code = "a = [1]
+b = a
+c = [1]
+print_sep(\" \", same(a, b), a == c, same(a, c), same(a, a[0, 1]))
+o = {=}
+f = fn(x) x
+print_sep(\" \", same(o, f(o)), same(o, {=}), same(1, 1), same(1, 1.5), same(nil, nil), same([], nil))
+s = \"ab\"
+t = s
+print_sep(\" \", same(s, t), same(s, \"a\" + \"b\"))";
+    assert_eq!(
+        run(code),
+        "true true false false\ntrue false true false true false\ntrue false\n"
+    );
+}
+
+#[test]
+fn fmt_float() {
+    assert_eq!(
+        run("print_sep(\" \", fmt_float(3.14159, 2), fmt_float(-2.5, 0), fmt_float(2.675, 1), fmt_float(-0.001, 2), fmt_float(7, 3), fmt_float(frac(1, 3), 4))"),
+        "3.14 -2 2.7 0.00 7.000 0.3333\n"
+    );
+    assert!(run("fmt_float(1.5, -1)").contains("non negative number of decimals"));
+}
+
+#[test]
+fn hex_oct_bin() {
+    assert_eq!(
+        run("print_sep(\" \", hex(255), bin(10), oct(8), hex(-255), bin(0), hex(-9223372036854775807 - 1))"),
+        "ff 1010 10 -ff 0 -8000000000000000\n"
+    );
+    assert!(run("hex(1.5)").contains("hex expects an integer, not 1.5"));
 }
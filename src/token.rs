@@ -1,5 +1,7 @@
 use std::ops::Add;
+use std::rc::Rc;
 
+use crate::bigint::BigInt;
 use crate::expr::Operator;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -10,6 +12,7 @@ pub struct Pos {
 
 #[derive(Debug)]
 pub struct Snippet {
+    pub source: Option<Rc<str>>,
     pub line: usize,
     pub col: usize,
     pub line_prefix: String,
@@ -21,10 +24,11 @@ impl Pos {
     pub fn new(start: usize, end: usize) -> Self {
         Self { start, end }
     }
-    pub fn extract<'c>(&self, code: &'c str) -> Snippet {
+    pub fn extract(&self, code: &str) -> Snippet {
         let line_start = code[..self.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
         let line_end = code[self.end..].find('\n').unwrap_or(code.len() - self.end) + self.end;
         Snippet {
+            source: None,
             line: code[..self.start].matches('\n').count() + 1,
             col: self.start + 1 - line_start,
             line_prefix: code[line_start..self.start].into(),
@@ -45,10 +49,13 @@ impl Add<Pos> for Pos {
 pub enum TokenType {
     // Literals
     Integer(i64),
+    BigInteger(BigInt),
     Float(f64),
     String(String),
     Identifier(String),
     Nil,
+    True,
+    False,
     // Keywords
     Return,
     If,
@@ -57,6 +64,14 @@ pub enum TokenType {
     Print,
     Read,
     While,
+    Do,
+    Break,
+    Continue,
+    In,
+    Times,
+    Let,
+    Try,
+    Catch,
     Func,
     Use,
     // Parenthesis
@@ -80,25 +95,36 @@ pub enum TokenType {
     AndAnd,
     Pipe,
     PipePipe,
+    Caret,
     // Operators
     Plus,
     Minus,
     Star,
     Slash,
+    SlashSlash,
     Percent,
     PlusEq,
     MinusEq,
     StarEq,
     SlashEq,
+    SlashSlashEq,
     PercentEq,
+    PlusPlus,
+    MinusMinus,
     LessLess,
     GreaterGreater,
+    AndEq,
+    PipeEq,
+    CaretEq,
+    LessLessEq,
+    GreaterGreaterEq,
     // End
     EOL,
     EOF,
     // Misc
     Eq,
     Comma,
+    Colon,
     Dot,
     Comment(String),
     // Error
@@ -112,10 +138,20 @@ impl TokenType {
             "else" => Self::Else,
             "for" => Self::For,
             "while" => Self::While,
+            "do" => Self::Do,
+            "break" => Self::Break,
+            "continue" => Self::Continue,
+            "in" => Self::In,
+            "times" => Self::Times,
+            "let" => Self::Let,
+            "try" => Self::Try,
+            "catch" => Self::Catch,
             "print" => Self::Print,
             "read" => Self::Read,
             "fn" => Self::Func,
             "nil" => Self::Nil,
+            "true" => Self::True,
+            "false" => Self::False,
             "return" => Self::Return,
             "use" => Self::Use,
             v => Self::Identifier(v.to_string()),
@@ -128,9 +164,13 @@ impl TokenType {
             TokenType::Minus => Operator::Sub,
             TokenType::Star => Operator::Mul,
             TokenType::Slash => Operator::Div,
+            TokenType::SlashSlash => Operator::FloorDiv,
             TokenType::Percent => Operator::Mod,
             TokenType::AndAnd => Operator::And,
             TokenType::PipePipe => Operator::Or,
+            TokenType::Caret => Operator::XOr,
+            TokenType::And => Operator::BitAnd,
+            TokenType::Pipe => Operator::BitOr,
             TokenType::EqEq => Operator::Eq,
             TokenType::BangEq => Operator::Neq,
             TokenType::Bang => Operator::Not,